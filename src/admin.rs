@@ -0,0 +1,33 @@
+use actix_web::{delete, patch, web, HttpResponse};
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+use crate::{auth::Identity, daynight::DayNightConfig, error::ApiError, motion::{MotionConfig, MotionRegion}, state::AppState};
+
+#[derive(Deserialize, ToSchema)]
+pub struct CameraUpdate {
+    pub alias: Option<String>,
+    pub disabled: Option<bool>,
+    pub stream_id: Option<u8>,
+    pub zones: Option<Vec<String>>,
+    pub daynight: Option<DayNightConfig>,
+    pub motion: Option<MotionConfig>,
+    pub regions: Option<Vec<MotionRegion>>,
+    pub record: Option<bool>
+}
+
+#[utoipa::path(delete, path = "/api/admin/cameras/{name}", description = "Removes the camera, stops its stream session and disconnects its viewers. A camera that is still running is added again on its next stat message.", params(("name" = String, Path)), responses((status = 200), (status = 403), (status = 404)))]
+#[delete("/api/admin/cameras/{name}")]
+async fn delete_camera(state: web::Data<AppState>, identity: Identity, cam_name: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    identity.authorize(&cam_name)?;
+    state.remove_camera(&cam_name).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[utoipa::path(patch, path = "/api/admin/cameras/{name}", params(("name" = String, Path)), request_body = CameraUpdate, responses((status = 200), (status = 400), (status = 403), (status = 404), (status = 422)))]
+#[patch("/api/admin/cameras/{name}")]
+async fn patch_camera(state: web::Data<AppState>, identity: Identity, cam_name: web::Path<String>, body: web::Json<CameraUpdate>) -> Result<HttpResponse, ApiError> {
+    identity.authorize(&cam_name)?;
+    state.update_camera(&cam_name, body.into_inner()).await?;
+    Ok(HttpResponse::Ok().finish())
+}
@@ -0,0 +1,91 @@
+use std::time::SystemTime;
+
+use actix_web::{get, web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::{admin::CameraUpdate, arming::{ArmActions, ArmMode, ArmRequest, ArmState, ArmStatus}, auth::Identity, commands::StateRequest, daynight::{effective_config, DayNightConfig}, error::ApiError, motion::{self, MotionConfig, MotionRegion, RegionKind}, notifiers::NotifierView, recording::SegmentView, schedule::{ScheduleRule, ScheduleTarget, ScheduleTrigger, ScheduleView}, sessions::StreamSessionInfo, storage::{Area, AreaStatus, CameraUsage, StorageStatus}, state::{AppState, CameraFilter, CameraInfo}, zones::{ZoneCommandResult, ZoneView}};
+
+#[derive(Serialize, ToSchema)]
+pub struct CameraView {
+    name: String,
+    alias: Option<String>,
+    disabled: bool,
+    zones: Vec<String>,
+    daynight: DayNightConfig,
+    night: Option<bool>,
+    override_until: Option<DateTime<Utc>>,
+    motion: MotionConfig,
+    regions: Vec<MotionRegion>,
+    record: Option<bool>,
+    ip: String,
+    stream_id: u8,
+    lum: u8,
+    online: bool,
+    last_stat: DateTime<Utc>,
+    last_frame_age_ms: Option<i64>,
+    settings: StateRequest,
+    viewers: u32,
+    streaming: bool,
+    movements: usize
+}
+
+impl CameraView {
+    fn from_camera(cam: &CameraInfo) -> Self {
+        let now: DateTime<Utc> = SystemTime::now().into();
+        CameraView {
+            name: cam.name.to_owned(),
+            alias: cam.alias.clone(),
+            disabled: cam.disabled,
+            zones: cam.zones.clone(),
+            daynight: effective_config(cam),
+            night: cam.daynight_state.night,
+            override_until: cam.daynight_state.override_until,
+            motion: motion::effective_config(cam),
+            regions: cam.regions.clone(),
+            record: cam.record,
+            ip: cam.ip.to_owned(),
+            stream_id: cam.stream_id,
+            lum: cam.lum,
+            online: cam.online,
+            last_stat: cam.last_stat,
+            last_frame_age_ms: cam.last_frame.map(|t| (now - t).num_milliseconds()),
+            settings: cam.settings.clone(),
+            viewers: cam.viewers,
+            streaming: cam.session_since.is_some(),
+            movements: cam.moves.len()
+        }
+    }
+}
+
+#[utoipa::path(get, path = "/api/cameras", responses((status = 200, body = [CameraView])))]
+#[get("/api/cameras")]
+async fn get_cameras(state: web::Data<AppState>, identity: Identity) -> Result<HttpResponse, ApiError> {
+    let mut cams = state.for_all_cameras(&CameraFilter::all(), CameraView::from_camera).await;
+    cams.retain(|cam| identity.can_view(&cam.name));
+    Ok(HttpResponse::Ok().json(cams))
+}
+
+#[utoipa::path(get, path = "/api/cameras/{name}", params(("name" = String, Path)), responses((status = 200, body = CameraView), (status = 404)))]
+#[get("/api/cameras/{name}")]
+async fn get_camera(state: web::Data<AppState>, identity: Identity, cam_name: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    identity.authorize(&cam_name)?;
+    match state.for_camera(&cam_name, CameraView::from_camera).await {
+        Some(cam) => Ok(HttpResponse::Ok().json(cam)),
+        None => Err(ApiError::NotFound(format!("camera {}", cam_name)))
+    }
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "camserver"),
+    paths(get_cameras, get_camera, crate::post_state, crate::get_movements, crate::get_streams, crate::admin::delete_camera, crate::admin::patch_camera, crate::zones::get_zones, crate::zones::post_zone_state, crate::schedule::get_schedules, crate::schedule::post_schedule, crate::schedule::put_schedule, crate::schedule::delete_schedule, crate::notifiers::get_notifiers, crate::notifiers::post_notifier_test, crate::arming::get_arming, crate::arming::put_arming, crate::arming::put_zone_arming, crate::recording::get_recordings, crate::recording::get_recording_frame, crate::recording::get_recording_play, crate::storage::get_storage),
+    components(schemas(CameraView, CameraUpdate, DayNightConfig, MotionConfig, MotionRegion, RegionKind, ZoneView, ZoneCommandResult, ScheduleRule, ScheduleView, ScheduleTarget, ScheduleTrigger, NotifierView, SegmentView, StorageStatus, AreaStatus, Area, CameraUsage, ArmMode, ArmActions, ArmState, ArmStatus, ArmRequest, StateRequest, StreamSessionInfo, crate::MovementResponse, crate::MovementItemResponse))
+)]
+struct ApiDoc;
+
+#[get("/api/openapi.json")]
+async fn get_openapi() -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(ApiDoc::openapi()))
+}
@@ -0,0 +1,214 @@
+use std::{collections::BTreeMap, env};
+
+use actix_web::{get, put, web, HttpResponse};
+use log::{info, warn};
+use rumqttc::QoS;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::Mutex;
+use utoipa::ToSchema;
+
+use crate::{auth::{Identity, Role}, error::ApiError, mqtt::MQTTState, persist::{load_arming, save_arming}, state::{AppState, CameraFilter}};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ArmMode {
+    Home,
+    #[default]
+    Away,
+    Night,
+    Disarmed
+}
+
+impl ArmMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ArmMode::Home => "home",
+            ArmMode::Away => "away",
+            ArmMode::Night => "night",
+            ArmMode::Disarmed => "disarmed"
+        }
+    }
+
+    fn strictness(&self) -> u8 {
+        match self {
+            ArmMode::Disarmed => 0,
+            ArmMode::Home => 1,
+            ArmMode::Night => 2,
+            ArmMode::Away => 3
+        }
+    }
+
+    fn parse(value: &str) -> Option<ArmMode> {
+        serde_json::from_value(Value::String(value.trim().to_lowercase())).ok()
+    }
+}
+
+#[derive(Serialize, Clone, Copy, Debug, ToSchema)]
+pub struct ArmActions {
+    pub snapshot: bool,
+    pub record: bool,
+    pub notify: bool
+}
+
+impl ArmActions {
+    fn from_env(mode: ArmMode, default: &str) -> Self {
+        let var = env::var(format!("ARM_{}_ACTIONS", mode.name().to_uppercase())).unwrap_or(default.to_string());
+        let actions: Vec<&str> = var.split(',').map(|a| a.trim()).collect();
+        ArmActions { snapshot: actions.contains(&"snapshot"), record: actions.contains(&"record"), notify: actions.contains(&"notify") }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, ToSchema)]
+pub struct ArmState {
+    pub system: ArmMode,
+    #[serde(default)]
+    pub zones: BTreeMap<String, ArmMode>
+}
+
+pub struct Arming {
+    state: Mutex<ArmState>,
+    policies: Vec<(ArmMode, ArmActions)>
+}
+
+impl Arming {
+    pub fn from_env() -> Self {
+        let policies = vec![
+            (ArmMode::Home, ArmActions::from_env(ArmMode::Home, "snapshot,record")),
+            (ArmMode::Away, ArmActions::from_env(ArmMode::Away, "snapshot,record,notify")),
+            (ArmMode::Night, ArmActions::from_env(ArmMode::Night, "snapshot,record,notify")),
+            (ArmMode::Disarmed, ArmActions::from_env(ArmMode::Disarmed, ""))
+        ];
+        Arming { state: Mutex::new(load_arming().unwrap_or_default()), policies }
+    }
+
+    pub async fn status(&self) -> ArmState {
+        self.state.lock().await.clone()
+    }
+
+    pub async fn mode_for(&self, zones: &[String]) -> ArmMode {
+        let state = self.state.lock().await;
+        zones.iter().filter_map(|zone| state.zones.get(zone).copied()).max_by_key(ArmMode::strictness).unwrap_or(state.system)
+    }
+
+    pub async fn actions_for(&self, zones: &[String]) -> ArmActions {
+        let mode = self.mode_for(zones).await;
+        self.policies.iter().find(|(m, _)| *m == mode).map(|(_, actions)| *actions).unwrap()
+    }
+
+    fn policies(&self) -> BTreeMap<String, ArmActions> {
+        self.policies.iter().map(|(mode, actions)| (mode.name().to_string(), *actions)).collect()
+    }
+
+    async fn set(&self, zone: Option<&str>, mode: Option<ArmMode>) -> ArmState {
+        let mut state = self.state.lock().await;
+        match (zone, mode) {
+            (None, Some(mode)) => state.system = mode,
+            (None, None) => {},
+            (Some(zone), Some(mode)) => { state.zones.insert(zone.trim().to_lowercase(), mode); },
+            (Some(zone), None) => { state.zones.remove(&zone.trim().to_lowercase()); }
+        }
+        save_arming(&state);
+        state.clone()
+    }
+}
+
+pub async fn set_arm_mode(state: &AppState, zone: Option<&str>, mode: Option<ArmMode>) -> ArmState {
+    let status = state.arming.set(zone, mode).await;
+    match zone {
+        Some(zone) => {
+            info!("Zone {} arm mode set to {:?}", zone, mode);
+            let body = mode.map(|m| m.name()).unwrap_or("");
+            state.mqtt_publish(&state.topics.arm_zone.topic(&zone.trim().to_lowercase()), body.as_bytes(), QoS::AtLeastOnce, true).await;
+        },
+        None => {
+            info!("System arm mode set to {:?}", status.system);
+            state.mqtt_publish(&state.topics.arm, status.system.name().as_bytes(), QoS::AtLeastOnce, true).await;
+        }
+    }
+    status
+}
+
+pub async fn mqtt_arm_set(state: AppState, topic: String, body: Value) {
+    let value = match &body {
+        Value::String(text) => text.to_owned(),
+        Value::Object(map) => map.get("mode").and_then(|m| m.as_str()).unwrap_or("").to_string(),
+        _ => String::new()
+    };
+    let zone = state.topics.arm_zone_set.camera(&topic).map(|z| z.to_string());
+    match ArmMode::parse(&value) {
+        Some(mode) => { set_arm_mode(&state, zone.as_deref(), Some(mode)).await; },
+        None if zone.is_some() && value.is_empty() => { set_arm_mode(&state, zone.as_deref(), None).await; },
+        None => warn!("Unknown arm mode {} on {}", value, topic)
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ArmStatus {
+    #[serde(flatten)]
+    state: ArmState,
+    policies: BTreeMap<String, ArmActions>
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ArmRequest {
+    mode: Option<ArmMode>
+}
+
+#[utoipa::path(get, path = "/api/arming", description = "A camera follows its zone's mode when the zone has one, otherwise the system mode. A camera in several zones with a mode set follows the strictest of them: away, then night, then home, then disarmed.", responses((status = 200, body = ArmStatus)))]
+#[get("/api/arming")]
+async fn get_arming(state: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(ArmStatus { state: state.arming.status().await, policies: state.arming.policies() }))
+}
+
+#[utoipa::path(put, path = "/api/arming", request_body = ArmRequest, responses((status = 200, body = ArmState), (status = 403), (status = 422)))]
+#[put("/api/arming")]
+async fn put_arming(state: web::Data<AppState>, identity: Identity, body: web::Json<ArmRequest>) -> Result<HttpResponse, ApiError> {
+    if identity.role < Role::Admin {
+        return Err(ApiError::Forbidden);
+    }
+    let mode = body.mode.ok_or(ApiError::Invalid("mode is required".to_string()))?;
+    Ok(HttpResponse::Ok().json(set_arm_mode(&state, None, Some(mode)).await))
+}
+
+#[utoipa::path(put, path = "/api/arming/zones/{zone}", params(("zone" = String, Path)), request_body = ArmRequest, responses((status = 200, body = ArmState), (status = 403), (status = 404)))]
+#[put("/api/arming/zones/{zone}")]
+async fn put_zone_arming(state: web::Data<AppState>, identity: Identity, zone: web::Path<String>, body: web::Json<ArmRequest>) -> Result<HttpResponse, ApiError> {
+    let visible = state.for_all_cameras(&CameraFilter::zone(&zone), |cam| identity.can_view(&cam.name)).await;
+    if visible.is_empty() {
+        return Err(ApiError::NotFound(format!("zone {}", zone)));
+    }
+    if !visible.contains(&true) {
+        return Err(ApiError::Forbidden);
+    }
+    Ok(HttpResponse::Ok().json(set_arm_mode(&state, Some(&zone), body.mode).await))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arming(system: ArmMode, zones: &[(&str, ArmMode)]) -> Arming {
+        let state = ArmState { system, zones: zones.iter().map(|(zone, mode)| (zone.to_string(), *mode)).collect() };
+        Arming { state: Mutex::new(state), policies: vec![] }
+    }
+
+    fn zones(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[actix_web::test]
+    async fn camera_without_zone_override_follows_system() {
+        let arming = arming(ArmMode::Home, &[("garden", ArmMode::Away)]);
+        assert_eq!(arming.mode_for(&zones(&["porch"])).await, ArmMode::Home);
+        assert_eq!(arming.mode_for(&[]).await, ArmMode::Home);
+    }
+
+    #[actix_web::test]
+    async fn strictest_zone_override_wins_regardless_of_order() {
+        let arming = arming(ArmMode::Away, &[("garden", ArmMode::Disarmed), ("porch", ArmMode::Night)]);
+        assert_eq!(arming.mode_for(&zones(&["garden", "porch"])).await, ArmMode::Night);
+        assert_eq!(arming.mode_for(&zones(&["porch", "garden"])).await, ArmMode::Night);
+        assert_eq!(arming.mode_for(&zones(&["garden"])).await, ArmMode::Disarmed);
+    }
+}
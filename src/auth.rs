@@ -0,0 +1,253 @@
+use std::{collections::HashMap, env, fs, future::{ready, Ready}, time::SystemTime};
+
+use actix_web::{body::{BoxBody, MessageBody}, cookie::{Cookie, SameSite}, dev::{Payload, ServiceRequest, ServiceResponse}, http::{header, Method}, middleware::Next, post, web, Error, FromRequest, HttpMessage, HttpRequest, HttpResponse, ResponseError};
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::{error::ApiError, state::AppState, tls::TlsSettings};
+
+const SESSION_COOKIE: &str = "camsession";
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Viewer,
+    Operator,
+    Admin
+}
+
+#[derive(Deserialize, Clone)]
+struct UserConfig {
+    username: String,
+    password_hash: String,
+    role: Role,
+    #[serde(default)]
+    cameras: Option<Vec<String>>
+}
+
+#[derive(Deserialize, Clone)]
+struct TokenConfig {
+    name: String,
+    sha256: String,
+    role: Role,
+    #[serde(default)]
+    cameras: Option<Vec<String>>
+}
+
+#[derive(Deserialize, Default)]
+struct AuthConfig {
+    #[serde(default)]
+    users: Vec<UserConfig>,
+    #[serde(default)]
+    tokens: Vec<TokenConfig>,
+    #[serde(default)]
+    session_hours: Option<i64>
+}
+
+#[derive(Clone, Debug)]
+pub struct Identity {
+    pub name: String,
+    pub role: Role,
+    cameras: Option<Vec<String>>
+}
+
+impl Identity {
+    fn anonymous_admin() -> Self {
+        Identity { name: "anonymous".to_string(), role: Role::Admin, cameras: None }
+    }
+
+    pub fn authorize(&self, cam: &str) -> Result<(), ApiError> {
+        if self.can_view(cam) { Ok(()) } else { Err(ApiError::Forbidden) }
+    }
+
+    pub fn can_view(&self, cam: &str) -> bool {
+        match &self.cameras {
+            Some(cameras) => cameras.iter().any(|c| c == cam || c == "*"),
+            None => true
+        }
+    }
+}
+
+impl FromRequest for Identity {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let identity = req.extensions().get::<Identity>().cloned();
+        ready(identity.ok_or(ApiError::Unauthorized.into()))
+    }
+}
+
+struct Session {
+    identity: Identity,
+    expires: DateTime<Utc>
+}
+
+pub struct Auth {
+    enabled: bool,
+    secure_cookies: bool,
+    config: AuthConfig,
+    sessions: Mutex<HashMap<String, Session>>
+}
+
+impl Auth {
+    pub fn from_env() -> Self {
+        match env::var("AUTH_FILE") {
+            Ok(path) => {
+                let config = match fs::read_to_string(&path).map_err(|e| e.to_string()).and_then(|text| serde_json::from_str::<AuthConfig>(&text).map_err(|e| e.to_string())) {
+                    Ok(config) => config,
+                    Err(err) => {
+                        error!("Could not load auth config from {}: {}", path, err);
+                        std::process::exit(1);
+                    }
+                };
+                info!("Auth enabled with {} users and {} tokens", config.users.len(), config.tokens.len());
+                Auth { enabled: true, secure_cookies: TlsSettings::from_env().is_some(), config, sessions: Mutex::new(HashMap::new()) }
+            },
+            Err(_) => {
+                warn!("AUTH_FILE not set, authentication disabled");
+                Auth { enabled: false, secure_cookies: false, config: AuthConfig::default(), sessions: Mutex::new(HashMap::new()) }
+            }
+        }
+    }
+
+    pub fn bind_address(&self) -> &'static str {
+        if self.enabled {
+            return "0.0.0.0";
+        }
+        if matches!(env::var("AUTH_DISABLED").as_deref(), Ok("true") | Ok("1")) {
+            warn!("AUTH_DISABLED set: every client on the network has admin access");
+            "0.0.0.0"
+        } else {
+            error!("AUTH_FILE not set: authentication disabled, listening on 127.0.0.1 only. Set AUTH_FILE, or AUTH_DISABLED=true to serve without authentication on all interfaces");
+            "127.0.0.1"
+        }
+    }
+
+    fn verify_password(&self, username: &str, password: &str) -> Option<Identity> {
+        let user = self.config.users.iter().find(|u| u.username == username)?;
+        let hash = PasswordHash::new(&user.password_hash).ok()?;
+        Argon2::default().verify_password(password.as_bytes(), &hash).ok()?;
+        Some(Identity { name: user.username.to_owned(), role: user.role, cameras: user.cameras.clone() })
+    }
+
+    fn verify_token(&self, token: &str) -> Option<Identity> {
+        let digest = format!("{:x}", Sha256::digest(token.as_bytes()));
+        let tok = self.config.tokens.iter().find(|t| t.sha256.eq_ignore_ascii_case(&digest))?;
+        Some(Identity { name: tok.name.to_owned(), role: tok.role, cameras: tok.cameras.clone() })
+    }
+
+    async fn create_session(&self, identity: Identity) -> String {
+        let id = Uuid::new_v4().to_string();
+        let now: DateTime<Utc> = SystemTime::now().into();
+        let expires = now + chrono::Duration::hours(self.config.session_hours.unwrap_or(24 * 7));
+        let mut sessions = self.sessions.lock().await;
+        sessions.retain(|_, s| s.expires > now);
+        sessions.insert(id.to_owned(), Session { identity, expires });
+        id
+    }
+
+    async fn session_identity(&self, id: &str) -> Option<Identity> {
+        let now: DateTime<Utc> = SystemTime::now().into();
+        let sessions = self.sessions.lock().await;
+        sessions.get(id).filter(|s| s.expires > now).map(|s| s.identity.clone())
+    }
+
+    async fn end_session(&self, id: &str) {
+        self.sessions.lock().await.remove(id);
+    }
+
+    async fn identify(&self, req: &ServiceRequest) -> Option<Identity> {
+        if !self.enabled {
+            return Some(Identity::anonymous_admin());
+        }
+        if let Some(auth) = req.headers().get(header::AUTHORIZATION).and_then(|h| h.to_str().ok()) {
+            if let Some(token) = auth.strip_prefix("Bearer ") {
+                return self.verify_token(token.trim());
+            }
+        }
+        match req.cookie(SESSION_COOKIE) {
+            Some(cookie) => self.session_identity(cookie.value()).await,
+            None => None
+        }
+    }
+}
+
+fn required_role(method: &Method, path: &str) -> Option<Role> {
+    if path == "/login" || path.starts_with("/css/") || path.starts_with("/js/") || path.starts_with("/img/") {
+        return None;
+    }
+    if path.starts_with("/api/admin/") {
+        Some(Role::Admin)
+    } else if *method == Method::GET {
+        Some(Role::Viewer)
+    } else if *method == Method::DELETE {
+        Some(Role::Admin)
+    } else if path == "/logout" {
+        Some(Role::Viewer)
+    } else {
+        Some(Role::Operator)
+    }
+}
+
+pub async fn auth_middleware(req: ServiceRequest, next: Next<impl MessageBody + 'static>) -> Result<ServiceResponse<BoxBody>, Error> {
+    let Some(required) = required_role(req.method(), req.path()) else {
+        return next.call(req).await.map(|res| res.map_into_boxed_body());
+    };
+    let state = req.app_data::<web::Data<AppState>>().unwrap().clone();
+    match state.auth.identify(&req).await {
+        Some(identity) if identity.role >= required => {
+            req.extensions_mut().insert(identity);
+            next.call(req).await.map(|res| res.map_into_boxed_body())
+        },
+        Some(identity) => {
+            info!("Denied {} {} to {}", req.method(), req.path(), identity.name);
+            Ok(req.into_response(ApiError::Forbidden.error_response()))
+        },
+        None if *req.method() == Method::GET && !req.path().starts_with("/api/") => {
+            Ok(req.into_response(HttpResponse::SeeOther().insert_header((header::LOCATION, "/login")).finish()))
+        },
+        None => Ok(req.into_response(ApiError::Unauthorized.error_response()))
+    }
+}
+
+#[derive(Deserialize)]
+struct LoginForm {
+    username: String,
+    password: String
+}
+
+#[post("/login")]
+async fn post_login(state: web::Data<AppState>, form: web::Form<LoginForm>) -> Result<HttpResponse, Error> {
+    let auth = state.auth.clone();
+    let LoginForm { username, password } = form.into_inner();
+    let (verifier, user) = (auth.clone(), username.clone());
+    match web::block(move || verifier.verify_password(&user, &password)).await? {
+        Some(identity) => {
+            info!("Login {}", identity.name);
+            let id = auth.create_session(identity).await;
+            let cookie = Cookie::build(SESSION_COOKIE, id).path("/").http_only(true).secure(auth.secure_cookies).same_site(SameSite::Strict).finish();
+            Ok(HttpResponse::SeeOther().cookie(cookie).insert_header((header::LOCATION, "/")).finish())
+        },
+        None => {
+            warn!("Failed login for {}", username);
+            Ok(HttpResponse::SeeOther().insert_header((header::LOCATION, "/login?failed=1")).finish())
+        }
+    }
+}
+
+#[post("/logout")]
+async fn post_logout(state: web::Data<AppState>, req: HttpRequest) -> Result<HttpResponse, Error> {
+    if let Some(cookie) = req.cookie(SESSION_COOKIE) {
+        state.auth.end_session(cookie.value()).await;
+    }
+    let mut removal = Cookie::new(SESSION_COOKIE, "");
+    removal.set_path("/");
+    removal.make_removal();
+    Ok(HttpResponse::SeeOther().cookie(removal).insert_header((header::LOCATION, "/login")).finish())
+}
@@ -0,0 +1,92 @@
+use std::net::IpAddr;
+
+use rumqttc::QoS;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::topics::TopicPolicy;
+
+pub const FRAME_SIZES: [(u8, &str); 14] = [
+    (0, "96x96"), (1, "QQVGA 160x120"), (2, "QCIF 176x144"), (3, "HQVGA 240x176"), (4, "240x240"),
+    (5, "QVGA 320x240"), (6, "CIF 400x296"), (7, "HVGA 480x320"), (8, "VGA 640x480"), (9, "SVGA 800x600"),
+    (10, "XGA 1024x768"), (11, "HD 1280x720"), (12, "SXGA 1280x1024"), (13, "UXGA 1600x1200")
+];
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, ToSchema)]
+pub struct StateRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ir: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flip: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub streamto: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub streamid: Option<u8>
+}
+
+impl StateRequest {
+    pub fn settings(&self) -> Option<StateRequest> {
+        let ret = StateRequest { ir: self.ir, filter: self.filter, size: self.size, flip: self.flip, ..Default::default() };
+        if ret.is_empty() { None } else { Some(ret) }
+    }
+
+    pub fn actions(&self) -> Option<StateRequest> {
+        let ret = StateRequest { streamto: self.streamto.clone(), streamid: self.streamid, ..Default::default() };
+        if ret.is_empty() { None } else { Some(ret) }
+    }
+
+    pub fn merge(&mut self, other: &StateRequest) {
+        if other.ir.is_some() { self.ir = other.ir; }
+        if other.filter.is_some() { self.filter = other.filter; }
+        if other.size.is_some() { self.size = other.size; }
+        if other.flip.is_some() { self.flip = other.flip; }
+        if other.streamto.is_some() { self.streamto = other.streamto.clone(); }
+        if other.streamid.is_some() { self.streamid = other.streamid; }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.is_empty() {
+            return Err("request contains no fields".to_string());
+        }
+        for (field, value) in [("ir", self.ir), ("filter", self.filter), ("flip", self.flip)] {
+            if let Some(v) = value {
+                if v > 1 {
+                    return Err(format!("{} must be 0 or 1, got {}", field, v));
+                }
+            }
+        }
+        if let Some(size) = self.size {
+            if !FRAME_SIZES.iter().any(|(s, _)| *s == size) {
+                return Err(format!("size {} is not a supported frame size", size));
+            }
+        }
+        if let Some(streamto) = &self.streamto {
+            if !streamto.is_empty() && streamto.parse::<IpAddr>().is_err() {
+                return Err(format!("streamto must be an IP address, got {}", streamto));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ir.is_none() && self.filter.is_none() && self.size.is_none() && self.flip.is_none() && self.streamto.is_none() && self.streamid.is_none()
+    }
+}
+
+pub struct CommandPolicies {
+    pub setting: TopicPolicy,
+    pub action: TopicPolicy
+}
+
+impl CommandPolicies {
+    pub fn from_env() -> Self {
+        CommandPolicies {
+            setting: TopicPolicy::from_env("MQTT_CMD_SETTING", QoS::AtLeastOnce, true),
+            action: TopicPolicy::from_env("MQTT_CMD_ACTION", QoS::AtLeastOnce, false)
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use std::{env, sync::LazyLock, time::SystemTime};
+
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{commands::StateRequest, state::{AppState, CameraInfo}};
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+#[serde(default)]
+pub struct DayNightConfig {
+    pub enabled: bool,
+    pub night_below: u8,
+    pub day_above: u8,
+    pub min_dwell_secs: i64,
+    pub override_secs: i64
+}
+
+static ENV_DEFAULT: LazyLock<DayNightConfig> = LazyLock::new(|| DayNightConfig {
+    enabled: matches!(env::var("DAYNIGHT_AUTO").as_deref(), Ok("true") | Ok("1")),
+    night_below: env_or("DAYNIGHT_NIGHT_LUM", 40),
+    day_above: env_or("DAYNIGHT_DAY_LUM", 80),
+    min_dwell_secs: env_or("DAYNIGHT_DWELL_SECS", 300),
+    override_secs: env_or("DAYNIGHT_OVERRIDE_SECS", 3600)
+});
+
+impl Default for DayNightConfig {
+    fn default() -> Self {
+        ENV_DEFAULT.clone()
+    }
+}
+
+impl DayNightConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.night_below >= self.day_above {
+            return Err("night_below must be lower than day_above".to_string());
+        }
+        if self.min_dwell_secs < 0 || self.override_secs < 0 {
+            return Err("durations must not be negative".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct DayNightState {
+    pub night: Option<bool>,
+    pub since: Option<DateTime<Utc>>,
+    pub override_until: Option<DateTime<Utc>>
+}
+
+impl DayNightState {
+    fn decide(&self, config: &DayNightConfig, lum: u8, now: DateTime<Utc>) -> Option<bool> {
+        let (night, since) = match self.override_until {
+            Some(until) if until > now => return None,
+            Some(_) => (None, None),
+            None => (self.night, self.since)
+        };
+        let target = match night {
+            Some(true) if lum > config.day_above => false,
+            Some(false) if lum < config.night_below => true,
+            None if lum < config.night_below => true,
+            None if lum > config.day_above => false,
+            _ => return None
+        };
+        if since.is_some_and(|since| (now - since).num_seconds() < config.min_dwell_secs) {
+            return None;
+        }
+        Some(target)
+    }
+
+    fn commit(&mut self, night: bool, now: DateTime<Utc>) {
+        self.night = Some(night);
+        self.since = Some(now);
+        if self.override_until.is_some_and(|until| until <= now) {
+            self.override_until = None;
+        }
+    }
+}
+
+pub fn mode_request(night: bool) -> StateRequest {
+    if night {
+        StateRequest { ir: Some(1), filter: Some(0), ..Default::default() }
+    } else {
+        StateRequest { ir: Some(0), filter: Some(1), ..Default::default() }
+    }
+}
+
+pub fn effective_config(cam_info: &CameraInfo) -> DayNightConfig {
+    cam_info.daynight.clone().unwrap_or_default()
+}
+
+pub async fn update_lum(state: &AppState, name: &str, lum: u8) {
+    let now: DateTime<Utc> = SystemTime::now().into();
+    let switch = state.for_mut_camera(name, |cam_info| {
+        let config = effective_config(cam_info);
+        if !config.enabled || cam_info.disabled {
+            return None;
+        }
+        cam_info.daynight_state.decide(&config, lum, now)
+    }).await.flatten();
+    if let Some(night) = switch {
+        info!("Switching {} to {} mode at lum {}", name, if night { "night" } else { "day" }, lum);
+        match state.send_command(name, &mode_request(night)).await {
+            Ok(()) => { state.for_mut_camera(name, |cam_info| cam_info.daynight_state.commit(night, now)).await; },
+            Err(err) => warn!("Could not switch day/night mode for {}: {}", name, err)
+        }
+    }
+}
+
+pub async fn manual_override(state: &AppState, name: &str, request: &StateRequest) {
+    if request.ir.is_none() && request.filter.is_none() {
+        return;
+    }
+    let now: DateTime<Utc> = SystemTime::now().into();
+    state.for_mut_camera(name, |cam_info| {
+        let config = effective_config(cam_info);
+        if config.enabled {
+            cam_info.daynight_state.override_until = Some(now + chrono::Duration::seconds(config.override_secs));
+        }
+    }).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> DayNightConfig {
+        DayNightConfig { enabled: true, night_below: 40, day_above: 80, min_dwell_secs: 300, override_secs: 3600 }
+    }
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000 + secs, 0).unwrap()
+    }
+
+    #[test]
+    fn first_reading_picks_mode_outside_band() {
+        let state = DayNightState::default();
+        assert_eq!(state.decide(&config(), 10, at(0)), Some(true));
+        assert_eq!(state.decide(&config(), 90, at(0)), Some(false));
+        assert_eq!(state.decide(&config(), 60, at(0)), None);
+    }
+
+    #[test]
+    fn hysteresis_keeps_mode_inside_band() {
+        let mut state = DayNightState::default();
+        state.commit(true, at(0));
+        assert_eq!(state.decide(&config(), 60, at(1000)), None);
+        assert_eq!(state.decide(&config(), 30, at(1000)), None);
+        assert_eq!(state.decide(&config(), 81, at(1000)), Some(false));
+        state.commit(false, at(1000));
+        assert_eq!(state.decide(&config(), 60, at(2000)), None);
+        assert_eq!(state.decide(&config(), 39, at(2000)), Some(true));
+    }
+
+    #[test]
+    fn dwell_delays_switch() {
+        let mut state = DayNightState::default();
+        state.commit(true, at(0));
+        assert_eq!(state.decide(&config(), 90, at(299)), None);
+        assert_eq!(state.decide(&config(), 90, at(300)), Some(false));
+    }
+
+    #[test]
+    fn decide_does_not_change_state_until_committed() {
+        let mut state = DayNightState::default();
+        state.commit(true, at(0));
+        assert_eq!(state.decide(&config(), 90, at(400)), Some(false));
+        assert_eq!(state.night, Some(true));
+        assert_eq!(state.decide(&config(), 90, at(410)), Some(false));
+    }
+
+    #[test]
+    fn override_suppresses_then_resets() {
+        let mut state = DayNightState::default();
+        state.commit(false, at(0));
+        state.override_until = Some(at(3600));
+        assert_eq!(state.decide(&config(), 10, at(1000)), None);
+        assert_eq!(state.decide(&config(), 10, at(3600)), Some(true));
+        assert_eq!(state.decide(&config(), 60, at(3600)), None);
+        state.commit(true, at(3600));
+        assert_eq!(state.override_until, None);
+    }
+}
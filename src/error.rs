@@ -0,0 +1,74 @@
+use std::fmt;
+
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use serde_json::json;
+
+#[derive(Debug)]
+pub enum ApiError {
+    NotFound(String),
+    Unauthorized,
+    Forbidden,
+    BadRequest(String),
+    Invalid(String),
+    MqttUnavailable,
+    Internal(String)
+}
+
+impl ApiError {
+    fn code(&self) -> &'static str {
+        match self {
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Unauthorized => "unauthorized",
+            ApiError::Forbidden => "forbidden",
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Invalid(_) => "invalid_request",
+            ApiError::MqttUnavailable => "mqtt_unavailable",
+            ApiError::Internal(_) => "internal_error"
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::NotFound(what) => write!(f, "{} not found", what),
+            ApiError::Unauthorized => write!(f, "not logged in"),
+            ApiError::Forbidden => write!(f, "access denied"),
+            ApiError::BadRequest(msg) | ApiError::Invalid(msg) => write!(f, "{}", msg),
+            ApiError::MqttUnavailable => write!(f, "MQTT broker is not connected"),
+            ApiError::Internal(msg) => write!(f, "{}", msg)
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden => StatusCode::FORBIDDEN,
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Invalid(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::MqttUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(json!({ "error": self.code(), "message": self.to_string() }))
+    }
+}
+
+impl From<serde_json::Error> for ApiError {
+    fn from(err: serde_json::Error) -> Self {
+        ApiError::Internal(err.to_string())
+    }
+}
+
+impl From<actix_web::Error> for ApiError {
+    fn from(err: actix_web::Error) -> Self {
+        ApiError::Internal(err.to_string())
+    }
+}
@@ -0,0 +1,100 @@
+use std::{env, time::Duration};
+
+use chrono::{DateTime, Utc};
+use log::info;
+use rumqttc::QoS;
+use serde::Serialize;
+use tokio::{task, time};
+
+use crate::{mqtt::MQTTState, state::AppState, topics::{TopicPolicy, Topics}};
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum UiEvent {
+    CameraAdded { camera: String, ip: String, stream_id: u8 },
+    Stat { camera: String, ip: String, lum: u8 },
+    Motion { camera: String, timestamp: DateTime<Utc>, regions: Vec<String> },
+    MotionEnded { camera: String, timestamp: DateTime<Utc>, end: DateTime<Utc> },
+    Online { camera: String, online: bool },
+    StreamStarted { camera: String }
+}
+
+impl UiEvent {
+    pub fn camera(&self) -> &str {
+        match self {
+            UiEvent::CameraAdded { camera, .. } => camera,
+            UiEvent::Stat { camera, .. } => camera,
+            UiEvent::Motion { camera, .. } => camera,
+            UiEvent::MotionEnded { camera, .. } => camera,
+            UiEvent::Online { camera, .. } => camera,
+            UiEvent::StreamStarted { camera } => camera
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct MotionEvent<'a> {
+    camera: &'a str,
+    timestamp: DateTime<Utc>,
+    active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    end: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    regions: &'a [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    snapshot_url: Option<String>
+}
+
+pub struct EventPublisher {
+    topics: Topics,
+    base_url: Option<String>,
+    motion: TopicPolicy,
+    online: TopicPolicy,
+    snapshot: TopicPolicy
+}
+
+impl EventPublisher {
+    pub fn from_env(topics: Topics) -> Self {
+        EventPublisher {
+            topics,
+            base_url: env::var("CAMSERVER_URL").ok(),
+            motion: TopicPolicy::from_env("MQTT_EVENT_MOTION", QoS::AtLeastOnce, false),
+            online: TopicPolicy::from_env("MQTT_EVENT_ONLINE", QoS::AtLeastOnce, true),
+            snapshot: TopicPolicy::from_env("MQTT_EVENT_SNAPSHOT", QoS::AtMostOnce, true)
+        }
+    }
+
+    pub async fn publish_motion<ST: MQTTState>(&self, state: &ST, cam: &str, timestamp: DateTime<Utc>, end: Option<DateTime<Utc>>, regions: &[String]) {
+        let event = MotionEvent {
+            camera: cam,
+            timestamp,
+            active: end.is_none(),
+            end,
+            regions,
+            snapshot_url: self.base_url.as_ref().map(|url| format!("{}/api/{}/snapshot", url, cam))
+        };
+        let body = serde_json::to_vec(&event).unwrap();
+        state.mqtt_publish(&self.topics.motion.topic(cam), &body, self.motion.qos, self.motion.retain).await;
+    }
+
+    pub async fn publish_online<ST: MQTTState>(&self, state: &ST, cam: &str, online: bool) {
+        let body = if online { "online" } else { "offline" };
+        state.mqtt_publish(&self.topics.online.topic(cam), body.as_bytes(), self.online.qos, self.online.retain).await;
+    }
+
+    pub async fn publish_snapshot<ST: MQTTState>(&self, state: &ST, cam: &str, jpeg: &[u8]) {
+        state.mqtt_publish(&self.topics.snapshot.topic(cam), jpeg, self.snapshot.qos, self.snapshot.retain).await;
+    }
+}
+
+pub fn spawn_presence_watchdog(state: AppState) {
+    let timeout = env::var("CAM_OFFLINE_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(120);
+    task::spawn(async move {
+        info!("Presence watchdog started, offline after {}s", timeout);
+        let mut interval = time::interval(Duration::from_secs(10));
+        loop {
+            interval.tick().await;
+            state.check_presence(chrono::Duration::seconds(timeout)).await;
+        }
+    });
+}
@@ -0,0 +1,111 @@
+use std::env;
+
+use log::info;
+use rumqttc::QoS;
+use serde_json::{json, Value};
+
+use crate::{mqtt::MQTTState, topics::Topics};
+
+pub struct HomeAssistant {
+    prefix: String,
+    base_url: Option<String>,
+    topics: Topics
+}
+
+impl HomeAssistant {
+    pub fn from_env(topics: Topics) -> Option<Self> {
+        let prefix = env::var("HA_DISCOVERY_PREFIX").ok()?;
+        let base_url = env::var("CAMSERVER_URL").ok();
+        Some(HomeAssistant { prefix, base_url, topics })
+    }
+
+    fn device(&self, cam: &str) -> Value {
+        let mut device = json!({
+            "identifiers": [format!("camserver_{}", cam)],
+            "name": cam,
+            "manufacturer": "camserver",
+            "model": "ESP32 camera"
+        });
+        if let Some(base_url) = &self.base_url {
+            device["configuration_url"] = json!(base_url);
+        }
+        device
+    }
+
+    fn config_topic(&self, component: &str, cam: &str, object: &str) -> String {
+        format!("{}/{}/camserver_{}/{}/config", self.prefix, component, cam, object)
+    }
+
+    fn camera_config(&self, cam: &str) -> Value {
+        json!({
+            "name": null,
+            "unique_id": format!("camserver_{}_camera", cam),
+            "topic": self.topics.snapshot.topic(cam),
+            "device": self.device(cam)
+        })
+    }
+
+    fn motion_config(&self, cam: &str) -> Value {
+        json!({
+            "name": "Motion",
+            "unique_id": format!("camserver_{}_motion", cam),
+            "device_class": "motion",
+            "state_topic": self.topics.motion.topic(cam),
+            "value_template": "{{ 'ON' if value_json.active else 'OFF' }}",
+            "device": self.device(cam)
+        })
+    }
+
+    fn switch_config(&self, cam: &str, field: &str, title: &str) -> Value {
+        json!({
+            "name": title,
+            "unique_id": format!("camserver_{}_{}", cam, field),
+            "command_topic": self.topics.cmd.topic(cam),
+            "payload_on": json!({ field: 1 }).to_string(),
+            "payload_off": json!({ field: 0 }).to_string(),
+            "optimistic": true,
+            "device": self.device(cam)
+        })
+    }
+
+    fn lum_config(&self, cam: &str) -> Value {
+        json!({
+            "name": "Luminance",
+            "unique_id": format!("camserver_{}_lum", cam),
+            "state_topic": self.topics.stat.topic(cam),
+            "value_template": "{{ value_json.lum }}",
+            "state_class": "measurement",
+            "device": self.device(cam)
+        })
+    }
+
+    pub async fn remove_camera<ST: MQTTState>(&self, state: &ST, cam: &str) {
+        info!("HA discovery removal for {}", cam);
+        let topics = [
+            self.config_topic("camera", cam, "camera"),
+            self.config_topic("binary_sensor", cam, "motion"),
+            self.config_topic("switch", cam, "ir"),
+            self.config_topic("switch", cam, "filter"),
+            self.config_topic("switch", cam, "flip"),
+            self.config_topic("sensor", cam, "lum")
+        ];
+        for topic in topics.iter() {
+            state.mqtt_publish(topic, &[], QoS::AtLeastOnce, true).await;
+        }
+    }
+
+    pub async fn publish_camera<ST: MQTTState>(&self, state: &ST, cam: &str) {
+        info!("HA discovery for {}", cam);
+        let configs = [
+            (self.config_topic("camera", cam, "camera"), self.camera_config(cam)),
+            (self.config_topic("binary_sensor", cam, "motion"), self.motion_config(cam)),
+            (self.config_topic("switch", cam, "ir"), self.switch_config(cam, "ir", "IR")),
+            (self.config_topic("switch", cam, "filter"), self.switch_config(cam, "filter", "Filter")),
+            (self.config_topic("switch", cam, "flip"), self.switch_config(cam, "flip", "Flip")),
+            (self.config_topic("sensor", cam, "lum"), self.lum_config(cam))
+        ];
+        for (topic, config) in configs.iter() {
+            state.mqtt_publish(topic, config.to_string().as_bytes(), QoS::AtLeastOnce, true).await;
+        }
+    }
+}
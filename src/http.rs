@@ -1,19 +1,51 @@
 use actix_web::{get, web, Error, HttpResponse};
-use maud::{html, DOCTYPE};
+use chrono::{DateTime, Utc};
+use maud::{html, PreEscaped, DOCTYPE};
+use serde::Deserialize;
+use serde_json::Value;
 
-use crate::state::AppState;
+use crate::{auth::{Identity, Role}, commands::{StateRequest, FRAME_SIZES}, daynight::{effective_config, DayNightConfig}, error::ApiError, motion::{self, MotionConfig, MotionRegion}, state::{AppState, CameraFilter, Movement}};
 
 struct CameraUIData {
     name: String,
+    display_name: String,
+    disabled: bool,
     ip: String,
-    stream_id: u8
+    stream_id: u8,
+    lum: u8,
+    online: bool,
+    zones: Vec<String>
 }
 
+type ZoneGroup<'a> = (Option<String>, Vec<(&'a CameraUIData, bool)>);
+
+const ARM_MODES: [&str; 4] = ["home", "away", "night", "disarmed"];
+
 #[get("/")]
-async fn index(state: web::Data<AppState>,) -> Result<HttpResponse, Error> {
-    let cams = state.for_all_cameras(|cam| {
-        CameraUIData { name: cam.name.to_owned(), ip: cam.ip.to_owned(), stream_id: cam.stream_id }
+async fn index(state: web::Data<AppState>, identity: Identity) -> Result<HttpResponse, Error> {
+    let mut cams = state.for_all_cameras(&CameraFilter::all(), |cam| {
+        CameraUIData { name: cam.name.to_owned(), display_name: cam.display_name().to_string(), disabled: cam.disabled, ip: cam.ip.to_owned(), stream_id: cam.stream_id, lum: cam.lum, online: cam.online, zones: cam.zones.clone() }
     }).await;
+    cams.retain(|cam| identity.can_view(&cam.name));
+    let mut groups: Vec<ZoneGroup> = vec![];
+    for cam_info in &cams {
+        let zones: Vec<Option<String>> = if cam_info.zones.is_empty() { vec![None] } else { cam_info.zones.iter().cloned().map(Some).collect() };
+        for zone in zones {
+            match groups.iter_mut().find(|(z, _)| *z == zone) {
+                Some((_, group)) => group.push((cam_info, false)),
+                None => groups.push((zone, vec![(cam_info, false)]))
+            }
+        }
+    }
+    groups.sort_by(|a, b| a.0.is_none().cmp(&b.0.is_none()).then(a.0.cmp(&b.0)));
+    let mut rendered: Vec<&str> = vec![];
+    for (_, group) in groups.iter_mut() {
+        for (cam_info, primary) in group.iter_mut() {
+            *primary = !rendered.contains(&cam_info.name.as_str());
+            rendered.push(&cam_info.name);
+        }
+    }
+    let arming = state.arming.status().await;
 
     let html = html! {
         (DOCTYPE)
@@ -23,47 +55,95 @@ async fn index(state: web::Data<AppState>,) -> Result<HttpResponse, Error> {
                 link rel="stylesheet" href="css/main.css" {}
             }
             body {
-                @for cam_info in &cams {
-                    div class="camcontainer" ip=(cam_info.ip) {
-                        div class="caminfo" {
-                            div class="camname" {(cam_info.name)}
-                            div class="camip" {(cam_info.ip)}
+                div class="userbar" {
+                    select onchange="armSet(null, this.value)" disabled[identity.role != Role::Admin] {
+                        @for mode in ARM_MODES {
+                            option value=(mode) selected[arming.system.name() == mode] {(mode)}
                         }
-                        div class="camimg" {
-                            img id=(format!("{}img", cam_info.name)) {}
+                    }
+                    span {(identity.name)}
+                    form method="post" action="/logout" {
+                        button type="submit" class="camctlbutton" {"Logout"}
+                    }
+                }
+                @for (zone, group) in &groups {
+                    div class="zonesection" {
+                        div class="zoneheader" {
+                            span class="zonename" {(zone.as_deref().unwrap_or("Unassigned"))}
+                            @if let Some(zone) = zone {
+                                a class="zonelink" href=(format!("/zone/{}", zone)) {"Mosaic"}
+                                button class="camctlbutton" data-zone=(zone) onclick="zoneState(this.dataset.zone, {ir: 1})" {"IR on"}
+                                button class="camctlbutton" data-zone=(zone) onclick="zoneState(this.dataset.zone, {ir: 0})" {"IR off"}
+                                select data-zone=(zone) onchange="armSet(this.dataset.zone, this.value)" {
+                                    option value="" {"system"}
+                                    @for mode in ARM_MODES {
+                                        option value=(mode) selected[arming.zones.get(zone).map(|m| m.name()) == Some(mode)] {(mode)}
+                                    }
+                                }
+                            }
                         }
-                        div class="camctl" {
-                            div class="camctlcol" {
-                                div class="camctlitem" {
-                                    div class="camctltitle" {"Filter"}
-                                    div class="camctlinput" {
-                                        input type="checkbox" onclick=(format!("filter('{}', this.checked)", cam_info.name)) {}
+                        @for (cam_info, primary) in group {
+                            @if *primary {
+                                div id=(format!("{}container", cam_info.name)) class=(if cam_info.online && !cam_info.disabled {"camcontainer"} else {"camcontainer offline"}) ip=(cam_info.ip) {
+                                    div class="caminfo" {
+                                        a class="camname" href=(format!("/cam/{}", cam_info.name)) {(cam_info.display_name)}
+                                        @if cam_info.disabled {
+                                            div class="camlum" {"disabled"}
+                                        }
+                                        div id=(format!("{}lum", cam_info.name)) class="camlum" {(format!("lum {}", cam_info.lum))}
+                                        div id=(format!("{}ip", cam_info.name)) class="camip" {(cam_info.ip)}
                                     }
-                                }  
-                                div class="camctlitem" {
-                                    div class="camctltitle" {"IR"}
-                                    div class="camctlinput" {
-                                        input type="checkbox" onclick=(format!("ir('{}', this.checked)", cam_info.name)) {}
+                                    div class="camimg" {
+                                        img id=(format!("{}img", cam_info.name)) {}
+                                        canvas id=(format!("{}canvas", cam_info.name)) class="camcanvas" {}
+                                        div id=(format!("{}latency", cam_info.name)) class="camlatency" {}
                                     }
-                                }  
-                                div class="camctlitem" {
-                                    div class="camctltitle" {"Flip"}
-                                    div class="camctlinput" {
-                                        input type="checkbox" onclick=(format!("flip('{}', this.checked)", cam_info.name)) {}
+                                    div class="camctl" {
+                                        div class="camctlcol" {
+                                            div class="camctlitem" {
+                                                div class="camctltitle" {"Filter"}
+                                                div class="camctlinput" {
+                                                    input type="checkbox" onclick=(format!("filter('{}', this.checked)", cam_info.name)) {}
+                                                }
+                                            }  
+                                            div class="camctlitem" {
+                                                div class="camctltitle" {"IR"}
+                                                div class="camctlinput" {
+                                                    input type="checkbox" onclick=(format!("ir('{}', this.checked)", cam_info.name)) {}
+                                                }
+                                            }  
+                                            div class="camctlitem" {
+                                                div class="camctltitle" {"Flip"}
+                                                div class="camctlinput" {
+                                                    input type="checkbox" onclick=(format!("flip('{}', this.checked)", cam_info.name)) {}
+                                                }
+                                            }                                                                                          
+                                        }
+                                        div class="camctlcolsep" { }
+                                        div class="camctlcol" {
+                                            button id=(format!("{}httpstreambut", cam_info.name)) class="camctlbutton" onclick=(format!("streamToggle('{}', 'http', 0)", cam_info.name)) { "HTTP Stream" }
+                                            button id=(format!("{}wsstreambut", cam_info.name)) class="camctlbutton" onclick=(format!("streamToggle('{}', 'ws', {})", cam_info.name, cam_info.stream_id)) { "WS Stream" }
+                                            select onchange=(format!("wsFps('{}', this.value)", cam_info.name)) {
+                                                @for fps in [1, 5, 10, 15, 30] {
+                                                    option value=(fps) selected[fps == 10] {(format!("{} fps", fps))}
+                                                }
+                                            }
+                                            button id=(format!("{}movementbut", cam_info.name)) class="camctlbutton" onclick=(format!("showMovement('{}')", cam_info.name)) { "Movement" }
+                                        }
                                     }
-                                }                                                                                          
-                            }
-                            div class="camctlcolsep" { }
-                            div class="camctlcol" {
-                                button id=(format!("{}httpstreambut", cam_info.name)) class="camctlbutton" onclick=(format!("streamToggle('{}', 'http', 0)", cam_info.name)) { "HTTP Stream" }
-                                button id=(format!("{}udpstreambut", cam_info.name)) class="camctlbutton" onclick=(format!("streamToggle('{}', 'udp', {})", cam_info.name, cam_info.stream_id)) { "UDP Stream" }
-                                button id=(format!("{}movementbut", cam_info.name)) class="camctlbutton" onclick=(format!("showMovement('{}')", cam_info.name)) { "Movement" }
+                                }    
+                                script { 
+                                    (format!("addCam('{}', '{}');", cam_info.name, cam_info.ip)) 
+                                }
+                            } @else {
+                                div class=(if cam_info.online && !cam_info.disabled {"camcontainer camref"} else {"camcontainer camref offline"}) data-cam=(cam_info.name) {
+                                    div class="caminfo" {
+                                        a class="camname" href=(format!("/cam/{}", cam_info.name)) {(cam_info.display_name)}
+                                        div class="camlum" {(format!("zones {}", cam_info.zones.join(", ")))}
+                                    }
+                                }
                             }
                         }
-                    }    
-                    script { 
-                        (format!("addCam('{}', '{}');", cam_info.name, cam_info.ip)) 
-                        (format!("setUdpIP('{}');", "192.168.1.130")) 
                     }
                 }
                 @if cams.len() == 0 {
@@ -71,6 +151,319 @@ async fn index(state: web::Data<AppState>,) -> Result<HttpResponse, Error> {
                         p {("No cams available")}
                     }
                 }
+                script { "listenEvents();" }
+            }
+        }
+    };
+    Ok(HttpResponse::Ok().body(html.0))
+}
+
+#[derive(Deserialize)]
+struct LoginQuery {
+    failed: Option<u8>
+}
+
+#[get("/login")]
+async fn login(query: web::Query<LoginQuery>) -> Result<HttpResponse, Error> {
+    let html = html! {
+        (DOCTYPE)
+        html {
+            head {
+                link rel="stylesheet" href="css/main.css" {}
+            }
+            body {
+                form class="login" method="post" action="/login" {
+                    @if query.failed.is_some() {
+                        div class="message" {"Invalid username or password"}
+                    }
+                    input type="text" name="username" placeholder="Username" {}
+                    input type="password" name="password" placeholder="Password" {}
+                    button type="submit" class="camctlbutton" {"Login"}
+                }
+            }
+        }
+    };
+    Ok(HttpResponse::Ok().body(html.0))
+}
+
+struct CameraDetailData {
+    name: String,
+    display_name: String,
+    alias: Option<String>,
+    disabled: bool,
+    zones: Vec<String>,
+    daynight: DayNightConfig,
+    night: Option<bool>,
+    motion: MotionConfig,
+    regions: Vec<MotionRegion>,
+    record: bool,
+    ip: String,
+    stream_id: u8,
+    lum: u8,
+    online: bool,
+    last_stat: DateTime<Utc>,
+    telemetry: Value,
+    settings: StateRequest,
+    moves: Vec<Movement>,
+    stream_started: Option<DateTime<Utc>>,
+    last_frame: Option<DateTime<Utc>>,
+    stream_frames: u64,
+    stream_bytes: u64
+}
+
+#[get("/cam/{cam}")]
+async fn camera_page(state: web::Data<AppState>, identity: Identity, cam_name: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    identity.authorize(&cam_name)?;
+    let cam_opt = state.for_camera(&cam_name, |cam| {
+        CameraDetailData {
+            name: cam.name.to_owned(),
+            display_name: cam.display_name().to_string(),
+            alias: cam.alias.clone(),
+            disabled: cam.disabled,
+            zones: cam.zones.clone(),
+            daynight: effective_config(cam),
+            night: cam.daynight_state.night,
+            motion: motion::effective_config(cam),
+            regions: cam.regions.clone(),
+            record: state.recordings.enabled_for(cam),
+            ip: cam.ip.to_owned(),
+            stream_id: cam.stream_id,
+            lum: cam.lum,
+            online: cam.online,
+            last_stat: cam.last_stat,
+            telemetry: cam.telemetry.clone(),
+            settings: cam.settings.clone(),
+            moves: cam.moves.iter().rev().take(20).cloned().collect(),
+            stream_started: cam.stream_started,
+            last_frame: cam.last_frame,
+            stream_frames: cam.stream_frames,
+            stream_bytes: cam.stream_bytes
+        }
+    }).await;
+    let Some(cam) = cam_opt else {
+        return Err(ApiError::NotFound(format!("camera {}", cam_name)));
+    };
+    let stream_secs = match (cam.stream_started, cam.last_frame) {
+        (Some(start), Some(last)) => (last - start).num_milliseconds() as f64 / 1000.0,
+        _ => 0.0
+    };
+    let daynight_json = serde_json::to_string(&cam.daynight)?;
+    let motion_json = serde_json::to_string(&cam.motion)?;
+    let regions_json = serde_json::to_string(&cam.regions)?.replace('<', "\\u003c");
+    let telemetry: Vec<(String, String)> = match &cam.telemetry {
+        Value::Object(map) => map.iter().map(|(k, v)| (k.to_owned(), v.to_string())).collect(),
+        _ => vec![]
+    };
+
+    let html = html! {
+        (DOCTYPE)
+        html {
+            head {
+                script src="/js/main.js" {}
+                link rel="stylesheet" href="/css/main.css" {}
+            }
+            body class="detail" {
+                div class="userbar" {
+                    a href="/" {"All cameras"}
+                    span {(identity.name)}
+                }
+                div id=(format!("{}container", cam.name)) class=(if cam.online {"camcontainer"} else {"camcontainer offline"}) {
+                    div class="caminfo" {
+                        div class="camname" {(cam.display_name)}
+                        div id=(format!("{}lum", cam.name)) class="camlum" {(format!("lum {}", cam.lum))}
+                        div id=(format!("{}ip", cam.name)) class="camip" {(cam.ip)}
+                    }
+                    div class="camimg" {
+                        img id=(format!("{}img", cam.name)) {}
+                        canvas id=(format!("{}canvas", cam.name)) class="camcanvas" {}
+                        div id=(format!("{}latency", cam.name)) class="camlatency" {}
+                    }
+                    div class="camctl" {
+                        button id=(format!("{}wsstreambut", cam.name)) class="camctlbutton" onclick=(format!("streamToggle('{}', 'ws', {})", cam.name, cam.stream_id)) { "WS Stream" }
+                    }
+                }
+                div class="detailcol" {
+                    div class="detailsection" {
+                        h3 {"Settings"}
+                        div class="camctlitem" {
+                            div class="camctltitle" {"Size"}
+                            select onchange=(format!("imgsize('{}', this.value)", cam.name)) {
+                                @if cam.settings.size.is_none() {
+                                    option selected {"-"}
+                                }
+                                @for (value, label) in FRAME_SIZES.iter() {
+                                    option value=(value) selected[cam.settings.size == Some(*value)] {(label)}
+                                }
+                            }
+                        }
+                        div class="camctlitem" {
+                            div class="camctltitle" {"IR"}
+                            input type="checkbox" checked[cam.settings.ir == Some(1)] onclick=(format!("ir('{}', this.checked)", cam.name)) {}
+                        }
+                        div class="camctlitem" {
+                            div class="camctltitle" {"Filter"}
+                            input type="checkbox" checked[cam.settings.filter == Some(1)] onclick=(format!("filter('{}', this.checked)", cam.name)) {}
+                        }
+                        div class="camctlitem" {
+                            div class="camctltitle" {"Flip"}
+                            input type="checkbox" checked[cam.settings.flip == Some(1)] onclick=(format!("flip('{}', this.checked)", cam.name)) {}
+                        }
+                    }
+                    @if identity.role == Role::Admin {
+                        div class="detailsection" {
+                            h3 {"Admin"}
+                            div class="camctlitem" {
+                                div class="camctltitle" {"Alias"}
+                                input type="text" value=(cam.alias.as_deref().unwrap_or("")) onchange=(format!("adminUpdate('{}', {{alias: this.value}})", cam.name)) {}
+                            }
+                            div class="camctlitem" {
+                                div class="camctltitle" {"Stream id"}
+                                input type="number" min="1" max="255" value=(cam.stream_id) onchange=(format!("adminUpdate('{}', {{stream_id: parseInt(this.value)}})", cam.name)) {}
+                            }
+                            div class="camctlitem" {
+                                div class="camctltitle" {"Zones"}
+                                input type="text" value=(cam.zones.join(", ")) onchange=(format!("adminUpdate('{}', {{zones: splitZones(this.value)}})", cam.name)) {}
+                            }
+                            div class="camctlitem" {
+                                div class="camctltitle" {"Auto day/night"}
+                                input type="checkbox" checked[cam.daynight.enabled] onclick=(format!("adminUpdate('{}', {{daynight: Object.assign({}, {{enabled: this.checked}})}})", cam.name, daynight_json)) {}
+                            }
+                            div class="camctlitem" {
+                                div class="camctltitle" {"Night below lum"}
+                                input type="number" min="0" max="255" value=(cam.daynight.night_below) onchange=(format!("adminUpdate('{}', {{daynight: Object.assign({}, {{night_below: parseInt(this.value)}})}})", cam.name, daynight_json)) {}
+                            }
+                            div class="camctlitem" {
+                                div class="camctltitle" {"Day above lum"}
+                                input type="number" min="0" max="255" value=(cam.daynight.day_above) onchange=(format!("adminUpdate('{}', {{daynight: Object.assign({}, {{day_above: parseInt(this.value)}})}})", cam.name, daynight_json)) {}
+                            }
+                            div class="camctlitem" {
+                                div class="camctltitle" {"Motion detection"}
+                                input type="checkbox" checked[cam.motion.enabled] onclick=(format!("adminUpdate('{}', {{motion: Object.assign({}, {{enabled: this.checked}})}})", cam.name, motion_json)) {}
+                            }
+                            div class="camctlitem" {
+                                div class="camctltitle" {"Continuous recording"}
+                                input type="checkbox" checked[cam.record] onclick=(format!("adminUpdate('{}', {{record: this.checked}})", cam.name)) {}
+                            }
+                            div class="camctlitem" {
+                                div class="camctltitle" {"Motion sensitivity"}
+                                input type="number" min="1" max="100" value=(cam.motion.sensitivity) onchange=(format!("adminUpdate('{}', {{motion: Object.assign({}, {{sensitivity: parseInt(this.value)}})}})", cam.name, motion_json)) {}
+                            }
+                            div class="camctlitem" {
+                                div class="camctltitle" {"Motion cooldown (s)"}
+                                input type="number" min="0" value=(cam.motion.cooldown_secs) onchange=(format!("adminUpdate('{}', {{motion: Object.assign({}, {{cooldown_secs: parseInt(this.value)}})}})", cam.name, motion_json)) {}
+                            }
+                            div class="camctlitem" {
+                                div class="camctltitle" {"Snapshots per event"}
+                                input type="number" min="0" max="20" value=(cam.motion.max_snapshots) onchange=(format!("adminUpdate('{}', {{motion: Object.assign({}, {{max_snapshots: parseInt(this.value)}})}})", cam.name, motion_json)) {}
+                            }
+                            div class="camctlitem" {
+                                div class="camctltitle" {"Disabled"}
+                                input type="checkbox" checked[cam.disabled] onclick=(format!("adminUpdate('{}', {{disabled: this.checked}})", cam.name)) {}
+                            }
+                            button class="camctlbutton" onclick=(format!("adminDelete('{}')", cam.name)) {"Delete"}
+                        }
+                        div class="detailsection" {
+                            h3 {"Motion regions"}
+                            div class="camimg regioneditor" {
+                                img id=(format!("{}regionimg", cam.name)) src=(format!("/api/{}/snapshot", cam.name)) {}
+                                canvas id=(format!("{}regioncanvas", cam.name)) class="regioncanvas" {}
+                            }
+                            div class="camctlitem" {
+                                input id=(format!("{}regionname", cam.name)) type="text" placeholder="Region name" {}
+                                select id=(format!("{}regionkind", cam.name)) {
+                                    option value="include" {"Detect"}
+                                    option value="exclude" {"Mask"}
+                                }
+                            }
+                            div class="camctlitem" {
+                                button class="camctlbutton" onclick=(format!("regionAdd('{}')", cam.name)) {"Add region"}
+                                button class="camctlbutton" onclick=(format!("regionUndo('{}')", cam.name)) {"Undo"}
+                                button class="camctlbutton" onclick=(format!("regionSave('{}')", cam.name)) {"Save"}
+                            }
+                            script { (PreEscaped(format!("regionEditor('{}', {});", cam.name, regions_json))) }
+                        }
+                    }
+                    div class="detailsection" {
+                        h3 {"Telemetry"}
+                        table {
+                            tr { td {"Online"} td {(cam.online)} }
+                            tr { td {"Last stat"} td {(cam.last_stat.format("%Y-%m-%d %H:%M:%S"))} }
+                            tr { td {"Mode"} td {(match cam.night { Some(true) => "night", Some(false) => "day", None => "-" })} }
+                            @for (key, value) in &telemetry {
+                                tr { td {(key)} td {(value)} }
+                            }
+                        }
+                    }
+                    div class="detailsection" {
+                        h3 {"Stream"}
+                        table {
+                            tr { td {"Frames"} td {(cam.stream_frames)} }
+                            tr { td {"Average fps"} td {(format!("{:.1}", if stream_secs > 0.0 { cam.stream_frames as f64 / stream_secs } else { 0.0 }))} }
+                            tr { td {"Average frame"} td {(format!("{} KB", cam.stream_bytes.checked_div(cam.stream_frames).unwrap_or(0) / 1024))} }
+                            tr { td {"Last frame"} td {(cam.last_frame.map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string()).unwrap_or("-".to_string()))} }
+                        }
+                    }
+                    div class="detailsection" {
+                        h3 {"Movements"}
+                        div class="timeline" {
+                            @for mvt in &cam.moves {
+                                div class="timelineitem" {
+                                    @for image in &mvt.images {
+                                        a href=(format!("/api/{}/images/{}", cam.name, image)) {
+                                            img class="thumb" src=(format!("/api/{}/images/{}", cam.name, image)) {}
+                                        }
+                                    }
+                                    span {(format!("{} - {} ({})", mvt.timestamp.format("%Y-%m-%d %H:%M:%S"), mvt.end.format("%H:%M:%S"), mvt.count))}
+                                    @if !mvt.regions.is_empty() {
+                                        span {(mvt.regions.join(", "))}
+                                    }
+                                }
+                            }
+                            @if cam.moves.is_empty() {
+                                span {"No movements"}
+                            }
+                        }
+                    }
+                }
+                script {
+                    (format!("addCam('{}', '{}');", cam.name, cam.ip))
+                    "listenEvents();"
+                }
+            }
+        }
+    };
+    Ok(HttpResponse::Ok().body(html.0))
+}
+
+#[get("/zone/{zone}")]
+async fn zone_page(state: web::Data<AppState>, identity: Identity, zone: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let filter = CameraFilter { enabled_only: true, ..CameraFilter::zone(&zone) };
+    let mut cams = state.for_all_cameras(&filter, |cam| (cam.name.to_owned(), cam.display_name().to_string())).await;
+    cams.retain(|(name, _)| identity.can_view(name));
+    if cams.is_empty() {
+        return Err(ApiError::NotFound(format!("zone {}", zone)));
+    }
+
+    let html = html! {
+        (DOCTYPE)
+        html {
+            head {
+                script src="/js/main.js" {}
+                link rel="stylesheet" href="/css/main.css" {}
+            }
+            body {
+                div class="userbar" {
+                    a href="/" {"All cameras"}
+                    span class="zonename" {(zone.as_str())}
+                }
+                div class="mosaic" {
+                    @for (name, display_name) in &cams {
+                        div class="mosaicitem" {
+                            a class="camname" href=(format!("/cam/{}", name)) {(display_name)}
+                            img src=(format!("/{}/stream", name)) {}
+                        }
+                    }
+                }
             }
         }
     };
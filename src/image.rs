@@ -1,21 +1,88 @@
-use std::{env, fs::File, io::Write, time::SystemTime};
+use std::{env, fs, path::Path, sync::Arc, time::SystemTime};
 
 use chrono::{DateTime, Utc};
+use log::{info, warn};
+use serde::Serialize;
 use tokio::task;
+use uuid::Uuid;
 
+use crate::{state::AppState, utils::safe_name};
 
+pub struct Capture {
+    pub trigger: String,
+    pub source: String,
+    pub event_id: String,
+    pub event_start: DateTime<Utc>
+}
 
-pub fn spawn_imager(cam: String, ip: String) {
+#[derive(Serialize)]
+struct ImageMetadata<'a> {
+    id: &'a str,
+    camera: &'a str,
+    file: &'a str,
+    timestamp: DateTime<Utc>,
+    trigger: &'a str,
+    source: &'a str,
+    event_id: &'a str,
+    event_start: DateTime<Utc>,
+    size: usize,
+    url: &'a str
+}
+
+fn write_image(folder: &Path, file: &str, data: &[u8], metadata: &ImageMetadata) -> std::io::Result<()> {
+    let path = folder.join(file);
+    if let Some(day) = path.parent() {
+        fs::create_dir_all(day)?;
+    }
+    fs::write(&path, data)?;
+    fs::write(path.with_extension("json"), serde_json::to_vec_pretty(metadata)?)
+}
+
+pub fn spawn_imager(state: AppState, cam: String, ip: String, capture: Capture) {
     task::spawn(async move {
-        let now: DateTime<Utc> = SystemTime::now().into();
-        let image_folder = env::var("IMAGE_FOLDER").unwrap();
+        let Ok(image_folder) = env::var("IMAGE_FOLDER") else {
+            warn!("IMAGE_FOLDER not set, not capturing {}", cam);
+            return;
+        };
         let url = format!("http://{}/picture", ip);
-        let filename = format!("{}-{}.jpg", cam, now.format("%Y-%m-%d-%H-%M-%S"));
-        let filepath = format!("{}/{}", image_folder, filename);
-        println!("Getting images from {} at {} and writting it to {}", cam, ip, filepath);
-        let resp = reqwest::get(url).await.unwrap();
-        let mut file = File::create(filepath).unwrap();
-        let bytes = resp.bytes().await.unwrap();
-        file.write_all(&bytes).unwrap();
+        let now: DateTime<Utc> = SystemTime::now().into();
+        let id = Uuid::new_v4().simple().to_string();
+        let filename = format!("{}/{}-{}.jpg", now.format("%Y-%m-%d"), now.format("%H-%M-%S-%3f"), &id[..12]);
+        let folder = Path::new(&image_folder).join(safe_name(&cam));
+        info!("Getting image from {} at {} into {}", cam, ip, folder.join(&filename).display());
+        let response = match reqwest::get(&url).await.and_then(|resp| resp.error_for_status()) {
+            Ok(resp) => resp.bytes().await,
+            Err(err) => Err(err)
+        };
+        let bytes = match response {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                warn!("Could not get image from {}: {}", cam, err);
+                return;
+            }
+        };
+        let metadata = ImageMetadata {
+            id: &id,
+            camera: &cam,
+            file: &filename,
+            timestamp: now,
+            trigger: &capture.trigger,
+            source: &capture.source,
+            event_id: &capture.event_id,
+            event_start: capture.event_start,
+            size: bytes.len(),
+            url: &url
+        };
+        if let Err(err) = write_image(&folder, &filename, &bytes, &metadata) {
+            warn!("Could not write image for {}: {}", cam, err);
+            return;
+        }
+        state.storage.wake();
+        let snapshot = Arc::new(bytes.to_vec());
+        state.for_mut_camera(&cam, |cam_info| {
+            cam_info.snapshot = snapshot.clone();
+            cam_info.add_movement_image(capture.event_start, &filename);
+        }).await;
+        state.events.publish_snapshot(&state, &cam, &snapshot).await;
     });
-}
\ No newline at end of file
+}
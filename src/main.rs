@@ -4,67 +4,144 @@ mod http;
 mod image;
 mod stream;
 mod utils;
+mod homeassistant;
+mod events;
+mod topics;
+mod commands;
+mod auth;
+mod tls;
+mod ws;
+mod sessions;
+mod api;
+mod error;
+mod persist;
+mod admin;
+mod zones;
+mod schedule;
+mod daynight;
+mod motion;
+mod notifiers;
+mod arming;
+mod recording;
+mod storage;
 
-use std::{io, sync::{mpsc::{self}, Arc}};
+use std::{env, fs, io, sync::Arc};
 use actix_files as af;
-use actix_web::{get, http::Error, post, web::{self, Bytes, Data}, App, HttpResponse, HttpServer};
+use actix_web::{delete, get, middleware::from_fn, post, web::{self, Bytes, Data}, App, HttpResponse, HttpServer};
+use auth::{auth_middleware, post_login, post_logout, Identity};
 use chrono::{DateTime, Utc};
-use http::index;
-use image::spawn_imager;
-use log::info;
-use mqtt::{MQTTServer, MQTTState};
+use commands::StateRequest;
+use error::ApiError;
+use events::spawn_presence_watchdog;
+use http::{camera_page, index, login, zone_page};
+use log::{info, warn};
+use mqtt::MQTTServer;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use state::AppState;
 use stream::{StreamReceiver};
+use tls::{redirect_to_https, TlsSettings};
+use sessions::{StreamSessionInfo, ViewerGuard, FRAME_QUEUE};
+use utoipa::ToSchema;
+use api::{get_camera, get_cameras, get_openapi};
+use admin::{delete_camera, patch_camera};
+use zones::{get_zones, post_zone_state};
+use motion::{movement_detected, spawn_motion_detector};
+use arming::{get_arming, mqtt_arm_set, put_arming, put_zone_arming};
+use recording::{get_recording_frame, get_recording_play, get_recordings, spawn_recorder};
+use storage::{get_metrics, get_storage, spawn_storage_manager};
+use notifiers::{get_notifiers, post_notifier_test};
+use schedule::{delete_schedule, get_schedules, post_schedule, put_schedule, spawn_scheduler};
+use tokio::sync::{broadcast::error::RecvError, mpsc};
+use ws::ws_stream;
+use utils::{safe_name, valid_name};
 use async_stream::stream;
 
 
-#[derive(Serialize, Deserialize, Debug)]
-struct StateRequest {
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub ir: Option<u8>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub filter: Option<u8>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub size: Option<u8>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub flip: Option<u8>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub streamto: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub streamid: Option<u8>
+#[utoipa::path(post, path = "/api/{cam}/state", params(("cam" = String, Path)), request_body = StateRequest, responses((status = 200), (status = 404), (status = 422), (status = 503)))]
+#[post("/api/{cam}/state")]
+async fn post_state(state: web::Data<AppState>, identity: Identity, cam: web::Path<String>, body: web::Json<StateRequest>) -> Result<HttpResponse, ApiError> {
+    identity.authorize(&cam)?;
+    if state.for_camera(&cam, |_| ()).await.is_none() {
+        return Err(ApiError::NotFound(format!("camera {}", cam)));
+    }
+    body.validate().map_err(ApiError::Invalid)?;
+    state.send_command(&cam, &body).await?;
+    daynight::manual_override(&state, &cam, &body).await;
+    Ok(HttpResponse::Ok().finish())
 }
 
-#[post("/api/{cam}/state")]
-async fn post_state(state: web::Data<AppState>, cam: web::Path<String>, body: web::Json<StateRequest>) -> Result<HttpResponse, Error> {
-    let topic = format!("home/cams/{}/cmd", &cam);
-    let mqtt_body = serde_json::to_string(&body).unwrap();
-    state.mqtt_publish(&topic, &mqtt_body).await;
+#[delete("/api/{cam}/retained")]
+async fn delete_retained(state: web::Data<AppState>, identity: Identity, cam: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    identity.authorize(&cam)?;
+    state.clear_retained_commands(&cam).await?;
     Ok(HttpResponse::Ok().finish())
 }
 
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
 struct MovementItemResponse {
-    timestamp: DateTime<Utc>
+    id: String,
+    timestamp: DateTime<Utc>,
+    end: DateTime<Utc>,
+    count: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image: Option<String>,
+    images: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    regions: Vec<String>
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
 struct MovementResponse {
     movements: Vec<MovementItemResponse>
 }
 
+#[utoipa::path(get, path = "/api/{cam}/movements", params(("cam" = String, Path)), responses((status = 200, body = MovementResponse)))]
 #[get("/api/{cam}/movements")]
-async fn get_movements(state: web::Data<AppState>, cam_name: web::Path<String>) -> Result<HttpResponse, Error> {
+async fn get_movements(state: web::Data<AppState>, identity: Identity, cam_name: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    identity.authorize(&cam_name)?;
     let mvts_opt: Option<Vec<MovementItemResponse>> = state.for_camera(cam_name.as_str(), |cam| {
-        cam.moves.iter().map(|m| MovementItemResponse{ timestamp: *m}).collect()
+        cam.moves.iter().map(|m| {
+            let images: Vec<String> = m.images.iter().map(|i| format!("/api/{}/images/{}", cam.name, i)).collect();
+            MovementItemResponse{ id: m.id.to_owned(), timestamp: m.timestamp, end: m.end, count: m.count, image: images.first().cloned(), images, regions: m.regions.clone() }
+        }).collect()
     }).await;
-    let resp = MovementResponse { movements: mvts_opt.unwrap_or(vec![]) };
+    let movements = mvts_opt.ok_or(ApiError::NotFound(format!("camera {}", cam_name)))?;
+    let resp = MovementResponse { movements };
     Ok(HttpResponse::Ok().json(resp))
 }
 
-fn prepare_http_bytes(data: Arc<Vec<u8>>) -> Bytes {
+#[get("/api/{cam}/snapshot")]
+async fn get_snapshot(state: web::Data<AppState>, identity: Identity, cam_name: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    identity.authorize(&cam_name)?;
+    let snapshot = state.for_camera(cam_name.as_str(), |cam| if cam.snapshot.is_empty() { cam.image.clone() } else { cam.snapshot.clone() }).await;
+    match snapshot {
+        Some(data) if !data.is_empty() => Ok(HttpResponse::Ok().content_type("image/jpeg").body(data.to_vec())),
+        _ => Err(ApiError::NotFound(format!("snapshot for {}", cam_name)))
+    }
+}
+
+#[get("/api/{cam}/images/{file:.*}")]
+async fn get_image(identity: Identity, path: web::Path<(String, String)>) -> Result<HttpResponse, ApiError> {
+    let (cam_name, file) = path.into_inner();
+    identity.authorize(&cam_name)?;
+    if !valid_name(&cam_name) {
+        return Err(ApiError::NotFound(format!("image {}", file)));
+    }
+    let image_folder = env::var("IMAGE_FOLDER").map_err(|_| ApiError::Internal("IMAGE_FOLDER not set".to_string()))?;
+    let filepath = match file.split_once('/') {
+        Some((day, name)) if safe_name(day) == day && safe_name(name) == name && name.ends_with(".jpg") => format!("{}/{}/{}/{}", image_folder, cam_name, day, name),
+        None if file.starts_with(&format!("{}-", cam_name)) && !file.contains("..") => format!("{}/{}", image_folder, file),
+        _ => return Err(ApiError::NotFound(format!("image {}", file)))
+    };
+    match fs::read(filepath) {
+        Ok(data) => Ok(HttpResponse::Ok().content_type("image/jpeg").body(data)),
+        Err(_) => Err(ApiError::NotFound(format!("image {}", file)))
+    }
+}
+
+pub(crate) fn prepare_http_bytes(data: Arc<Vec<u8>>) -> Bytes {
     let http_len = &format!("Content-Length: {}\r\n\r\n", data.as_ref().len())[..];
     let data_slice = &data.as_ref()[..];
     let d = ["\r\n--123456789000000000000987654321\r\n".as_bytes(), "Content-Type: image/jpeg\r\n".as_bytes(), http_len.as_bytes(), data_slice].concat();
@@ -72,18 +149,49 @@ fn prepare_http_bytes(data: Arc<Vec<u8>>) -> Bytes {
 }
  
 #[get("/{cam}/stream")]
-async fn get_stream(state: web::Data<AppState>, cam_name: web::Path<String>) -> Result<HttpResponse, Error> {
-    let (tx, rx) = mpsc::channel::<Arc<Vec<u8>>>();
+async fn get_stream(state: web::Data<AppState>, identity: Identity, cam_name: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    identity.authorize(&cam_name)?;
+    let guard = ViewerGuard::join(&state, cam_name.as_str()).await?;
+    let (tx, mut rx) = mpsc::channel::<Arc<Vec<u8>>>(FRAME_QUEUE);
     state.for_mut_camera(cam_name.as_str(), |cam| {
         cam.add_sender(tx.clone());
     }).await;
     Ok(HttpResponse::Ok()
         .content_type("multipart/x-mixed-replace;boundary=123456789000000000000987654321")
         .streaming(stream! {
+            let _guard = guard;
+            while let Some(data) = rx.recv().await {
+                yield Ok::<Bytes, ApiError>(prepare_http_bytes(data));
+            }
+        })
+    )
+}
+
+#[utoipa::path(get, path = "/api/streams", responses((status = 200, body = [StreamSessionInfo])))]
+#[get("/api/streams")]
+async fn get_streams(state: web::Data<AppState>, identity: Identity) -> Result<HttpResponse, ApiError> {
+    let mut sessions = state.stream_sessions().await;
+    sessions.retain(|s| identity.can_view(&s.camera));
+    Ok(HttpResponse::Ok().json(sessions))
+}
+
+#[get("/api/events")]
+async fn get_events(state: web::Data<AppState>, identity: Identity) -> Result<HttpResponse, ApiError> {
+    let mut rx = state.subscribe_ui_events();
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(stream! {
+            yield Ok::<Bytes, ApiError>(Bytes::from(": connected\n\n"));
             loop {
-                match rx.recv() {
-                    Ok(data) => yield Ok(prepare_http_bytes(data)),
-                    Err(err) => yield Err(err),
+                match rx.recv().await {
+                    Ok(event) if identity.can_view(event.camera()) => {
+                        let json = serde_json::to_string(&event).unwrap();
+                        yield Ok(Bytes::from(format!("data: {}\n\n", json)));
+                    },
+                    Ok(_) => {},
+                    Err(RecvError::Lagged(_)) => {},
+                    Err(RecvError::Closed) => break
                 }
             }
         })
@@ -91,20 +199,23 @@ async fn get_stream(state: web::Data<AppState>, cam_name: web::Path<String>) ->
 }
 
 async fn mqtt_cam_stat(state: AppState, topic: String, body: Value) {
-    let name = &topic[10..topic.len() - 5];
-    let ip = body["ip"].as_str().unwrap();
+    let Some(name) = state.topics.stat.camera(&topic) else { return };
+    let Some(ip) = body["ip"].as_str() else {
+        warn!("Ignoring stat from {} without ip: {}", name, body);
+        return;
+    };
     let lum = if let Some(r) = body["lum"].as_u64() {r as u8} else {0};
     info!("Cam Stat {}: ip {}, lum {}", name, ip, lum);
-    state.set_camera_stat(name, ip, lum).await;
+    state.set_camera_stat(name, ip, lum, body.clone()).await;
+    if body["lum"].is_u64() {
+        daynight::update_lum(&state, name, lum).await;
+    }
 }
 
 async fn mqtt_cam_move(state: AppState, topic: String, body: Value) {
-    let name = &topic[10..topic.len() - 5];
+    let Some(name) = state.topics.moves.camera(&topic) else { return };
     info!("Cam move {}: {}", name, body);
-    state.for_mut_camera(name, |cam| {
-        cam.record_movement();
-        spawn_imager(cam.name.to_owned(), cam.ip.to_owned());
-    }).await;
+    movement_detected(&state, name, "mqtt", vec![]).await;
 }
 
 #[actix_web::main]
@@ -113,25 +224,90 @@ async fn main() -> io::Result<()> {
     let state = AppState::new();
 
     let mqtt_server = MQTTServer::new(state.clone()).await;
-    mqtt_server.subscribe("home/cams/+/stat", mqtt_cam_stat).await;
-    mqtt_server.subscribe("home/cams/+/move", mqtt_cam_move).await;
+    mqtt_server.subscribe(&state.topics.stat.subscription(), mqtt_cam_stat).await;
+    mqtt_server.subscribe(&state.topics.moves.subscription(), mqtt_cam_move).await;
+    mqtt_server.subscribe(&state.topics.arm_set, mqtt_arm_set).await;
+    mqtt_server.subscribe(&state.topics.arm_zone_set.subscription(), mqtt_arm_set).await;
 
     StreamReceiver::init(state.clone());
+    spawn_presence_watchdog(state.clone());
+    spawn_scheduler(state.clone());
+    spawn_motion_detector(state.clone());
+    spawn_recorder(state.clone());
+    spawn_storage_manager(state.clone());
     
-    HttpServer::new(move || {
+    let host = state.auth.bind_address();
+    let server = HttpServer::new(move || {
         App::new()
         .app_data(Data::new(state.clone()))
+        .app_data(web::JsonConfig::default().error_handler(|err, _req| ApiError::Invalid(err.to_string()).into()))
+        .wrap(from_fn(auth_middleware))
         .service(index)
+        .service(login)
+        .service(post_login)
+        .service(post_logout)
         .service(post_state)
+        .service(delete_retained)
         .service(get_movements)
+        .service(get_snapshot)
+        .service(get_image)
+        .service(camera_page)
+        .service(get_events)
         .service(get_stream)
+        .service(ws_stream)
+        .service(get_streams)
+        .service(get_cameras)
+        .service(get_camera)
+        .service(get_openapi)
+        .service(delete_camera)
+        .service(patch_camera)
+        .service(get_zones)
+        .service(post_zone_state)
+        .service(zone_page)
+        .service(get_schedules)
+        .service(post_schedule)
+        .service(put_schedule)
+        .service(delete_schedule)
+        .service(get_notifiers)
+        .service(post_notifier_test)
+        .service(get_arming)
+        .service(put_arming)
+        .service(put_zone_arming)
+        .service(get_recordings)
+        .service(get_recording_frame)
+        .service(get_recording_play)
+        .service(get_storage)
+        .service(get_metrics)
         .service(af::Files::new("/css", "./static/css").show_files_listing())
         .service(af::Files::new("/img", "./static/img").show_files_listing())
         .service(af::Files::new("/js", "./static/js").show_files_listing())
     })
-    .max_buffer_size(10_000)
-    .bind(("0.0.0.0", 8080))?
-    .run()
-    .await
+    .max_buffer_size(10_000);
+
+    match TlsSettings::from_env() {
+        Some(settings) => {
+            if let Some(redirect_port) = settings.redirect_port {
+                let https_port = settings.port;
+                let redirect = HttpServer::new(move || {
+                    App::new()
+                    .app_data(Data::new(https_port))
+                    .default_service(web::to(redirect_to_https))
+                })
+                .bind((host, redirect_port))?
+                .run();
+                actix_web::rt::spawn(redirect);
+            }
+            server
+            .bind_rustls_0_23((host, settings.port), tls::server_config(&settings)?)?
+            .run()
+            .await
+        },
+        None => {
+            server
+            .bind((host, 8080))?
+            .run()
+            .await
+        }
+    }
 }
 
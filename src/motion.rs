@@ -0,0 +1,380 @@
+use std::{collections::HashMap, env, sync::Arc, time::{Duration, Instant, SystemTime}};
+
+use chrono::{DateTime, Utc};
+
+use jpeg_decoder::{Decoder, PixelFormat};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::{mpsc, Mutex}, task, time};
+use utoipa::ToSchema;
+
+use crate::{events::UiEvent, image::{spawn_imager, Capture}, notifiers::notify_motion, state::{AppState, CameraInfo}};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+#[serde(default)]
+pub struct MotionConfig {
+    pub enabled: bool,
+    pub sensitivity: u8,
+    pub cooldown_secs: i64,
+    pub max_snapshots: u32,
+    pub snapshot_interval_secs: i64
+}
+
+impl Default for MotionConfig {
+    fn default() -> Self {
+        MotionConfig {
+            enabled: matches!(env::var("MOTION_DETECT").as_deref(), Ok("true") | Ok("1")),
+            sensitivity: env::var("MOTION_SENSITIVITY").ok().and_then(|v| v.parse::<u8>().ok()).unwrap_or(50).clamp(1, 100),
+            cooldown_secs: env::var("MOTION_COOLDOWN_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(30),
+            max_snapshots: env::var("MOTION_MAX_SNAPSHOTS").ok().and_then(|v| v.parse().ok()).unwrap_or(3),
+            snapshot_interval_secs: env::var("MOTION_SNAPSHOT_INTERVAL_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(5)
+        }
+    }
+}
+
+impl MotionConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(1..=100).contains(&self.sensitivity) {
+            return Err("sensitivity must be between 1 and 100".to_string());
+        }
+        if self.cooldown_secs < 0 || self.snapshot_interval_secs < 0 {
+            return Err("durations must not be negative".to_string());
+        }
+        if self.max_snapshots > 20 {
+            return Err("max_snapshots must be at most 20".to_string());
+        }
+        Ok(())
+    }
+
+    fn pixel_threshold(&self) -> u8 {
+        10 + 100u8.saturating_sub(self.sensitivity) / 2
+    }
+
+    fn area_threshold(&self) -> f64 {
+        0.002 + 100u8.saturating_sub(self.sensitivity) as f64 * 0.0005
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RegionKind {
+    Include,
+    Exclude
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct MotionRegion {
+    pub name: String,
+    pub kind: RegionKind,
+    pub points: Vec<[f32; 2]>
+}
+
+impl MotionRegion {
+    fn contains(&self, x: f32, y: f32) -> bool {
+        if self.points.len() < 3 {
+            return false;
+        }
+        let mut inside = false;
+        let mut j = self.points.len() - 1;
+        for (i, [xi, yi]) in self.points.iter().enumerate() {
+            let [xj, yj] = self.points[j];
+            if (*yi > y) != (yj > y) && x < (xj - xi) * (y - yi) / (yj - yi) + xi {
+                inside = !inside;
+            }
+            j = i;
+        }
+        inside
+    }
+}
+
+pub fn validate_regions(regions: &[MotionRegion]) -> Result<(), String> {
+    for (i, region) in regions.iter().enumerate() {
+        if region.name.trim().is_empty() {
+            return Err("region name must not be empty".to_string());
+        }
+        if regions[..i].iter().any(|r| r.name == region.name) {
+            return Err(format!("duplicate region {}", region.name));
+        }
+        if region.points.len() < 3 {
+            return Err(format!("region {} needs at least 3 points", region.name));
+        }
+        if region.points.iter().flatten().any(|c| !(0.0..=1.0).contains(c)) {
+            return Err(format!("region {} points must be between 0 and 1", region.name));
+        }
+    }
+    Ok(())
+}
+
+pub fn effective_config(cam_info: &CameraInfo) -> MotionConfig {
+    cam_info.motion.clone().unwrap_or_default()
+}
+
+struct MotionFrame {
+    camera: String,
+    config: MotionConfig,
+    regions: Vec<MotionRegion>,
+    data: Arc<Vec<u8>>
+}
+
+struct LumaFrame {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>
+}
+
+pub struct MotionDetector {
+    sender: mpsc::Sender<MotionFrame>,
+    receiver: Mutex<Option<mpsc::Receiver<MotionFrame>>>
+}
+
+impl MotionDetector {
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::channel(8);
+        MotionDetector { sender, receiver: Mutex::new(Some(receiver)) }
+    }
+
+    pub fn submit(&self, camera: &str, config: &MotionConfig, regions: &[MotionRegion], data: Arc<Vec<u8>>) {
+        let _ = self.sender.try_send(MotionFrame { camera: camera.to_string(), config: config.clone(), regions: regions.to_vec(), data });
+    }
+}
+
+fn decode_luma(data: &[u8], width: u16) -> Option<LumaFrame> {
+    let mut decoder = Decoder::new(data);
+    let (w, h) = decoder.scale(width, 1).ok()?;
+    let decoded = decoder.decode().ok()?;
+    let pixels = match decoder.info()?.pixel_format {
+        PixelFormat::L8 => decoded,
+        PixelFormat::RGB24 => decoded.chunks_exact(3).map(|p| ((p[0] as u32 * 299 + p[1] as u32 * 587 + p[2] as u32 * 114) / 1000) as u8).collect(),
+        _ => return None
+    };
+    Some(LumaFrame { width: w as usize, height: h as usize, pixels })
+}
+
+fn detect(prev: &LumaFrame, cur: &LumaFrame, config: &MotionConfig, regions: &[MotionRegion]) -> Option<(Vec<String>, f64)> {
+    if prev.width != cur.width || prev.height != cur.height || cur.pixels.is_empty() {
+        return None;
+    }
+    let threshold = config.pixel_threshold();
+    let includes: Vec<&MotionRegion> = regions.iter().filter(|r| r.kind == RegionKind::Include).collect();
+    let excludes: Vec<&MotionRegion> = regions.iter().filter(|r| r.kind == RegionKind::Exclude).collect();
+    let mut counts = vec![(0usize, 0usize); includes.len().max(1)];
+    let (mut total, mut changed_total) = (0usize, 0usize);
+    for (i, (a, b)) in prev.pixels.iter().zip(cur.pixels.iter()).enumerate() {
+        let x = ((i % cur.width) as f32 + 0.5) / cur.width as f32;
+        let y = ((i / cur.width) as f32 + 0.5) / cur.height as f32;
+        if excludes.iter().any(|r| r.contains(x, y)) {
+            continue;
+        }
+        let changed = a.abs_diff(*b) > threshold;
+        total += 1;
+        changed_total += changed as usize;
+        if includes.is_empty() {
+            counts[0].0 += 1;
+            counts[0].1 += changed as usize;
+        } else {
+            for (n, region) in includes.iter().enumerate() {
+                if region.contains(x, y) {
+                    counts[n].0 += 1;
+                    counts[n].1 += changed as usize;
+                }
+            }
+        }
+    }
+    if total == 0 || changed_total as f64 / total as f64 > 0.8 {
+        return None;
+    }
+    let ratios: Vec<f64> = counts.iter().map(|(total, changed)| if *total > 0 { *changed as f64 / *total as f64 } else { 0.0 }).collect();
+    let max = ratios.iter().cloned().fold(0.0, f64::max);
+    if max <= config.area_threshold() {
+        return None;
+    }
+    let names = includes.iter().zip(ratios.iter()).filter(|(_, ratio)| **ratio > config.area_threshold()).map(|(r, _)| r.name.to_owned()).collect();
+    Some((names, max))
+}
+
+pub async fn movement_detected(state: &AppState, name: &str, source: &str, regions: Vec<String>) {
+    let Some(zones) = state.for_camera(name, |cam| cam.zones.clone()).await else { return };
+    let actions = state.arming.actions_for(&zones).await;
+    let moved = state.for_mut_camera(name, |cam| {
+        if cam.disabled {
+            return None;
+        }
+        let config = effective_config(cam);
+        let update = cam.record_movement(&regions, &config);
+        let capturing = update.snapshot && actions.snapshot;
+        if capturing {
+            let capture = Capture { trigger: "motion".to_string(), source: source.to_string(), event_id: update.id.to_owned(), event_start: update.timestamp };
+            spawn_imager(state.clone(), cam.name.to_owned(), cam.ip.to_owned(), capture);
+        }
+        Some((update, capturing, config.cooldown_secs))
+    }).await.flatten();
+    let Some((update, capturing, cooldown)) = moved else { return };
+    if update.started {
+        info!("Movement on {} from {} {:?}", name, source, regions);
+        state.events.publish_motion(state, name, update.timestamp, None, &regions).await;
+        if actions.notify {
+            notify_motion(state, name, update.timestamp, &regions, capturing);
+        }
+        state.notify(UiEvent::Motion { camera: name.to_string(), timestamp: update.timestamp, regions });
+        task::spawn(finish_movement(state.clone(), name.to_string(), update.timestamp, cooldown));
+    }
+}
+
+async fn finish_movement(state: AppState, name: String, timestamp: DateTime<Utc>, cooldown: i64) {
+    let mut wait = cooldown;
+    loop {
+        time::sleep(Duration::from_secs(wait.max(1) as u64)).await;
+        let status = state.for_camera(&name, |cam| {
+            let now: DateTime<Utc> = SystemTime::now().into();
+            let mvt = cam.moves.iter().rev().find(|m| m.timestamp == timestamp)?;
+            let remaining = cooldown - (now - mvt.end).num_seconds();
+            Some(if remaining > 0 { Err(remaining) } else { Ok((mvt.end, mvt.regions.clone())) })
+        }).await.flatten();
+        match status {
+            Some(Ok((end, regions))) => {
+                info!("Movement on {} ended", name);
+                state.events.publish_motion(&state, &name, timestamp, Some(end), &regions).await;
+                state.notify(UiEvent::MotionEnded { camera: name, timestamp, end });
+                return;
+            },
+            Some(Err(remaining)) => wait = remaining,
+            None => return
+        }
+    }
+}
+
+pub fn spawn_motion_detector(state: AppState) {
+    let interval = Duration::from_millis(1000 / env::var("MOTION_FPS").ok().and_then(|v| v.parse().ok()).unwrap_or(2u64).max(1));
+    let width: u16 = env::var("MOTION_WIDTH").ok().and_then(|v| v.parse().ok()).unwrap_or(160);
+    task::spawn(async move {
+        let Some(mut receiver) = state.motion.receiver.lock().await.take() else { return };
+        info!("Motion detector started");
+        let mut previous: HashMap<String, (Instant, LumaFrame)> = HashMap::new();
+        while let Some(frame) = receiver.recv().await {
+            if previous.get(&frame.camera).is_some_and(|(at, _)| at.elapsed() < interval) {
+                continue;
+            }
+            let data = frame.data.clone();
+            let Ok(Some(luma)) = task::spawn_blocking(move || decode_luma(&data, width)).await else {
+                warn!("Could not decode frame from {} for motion detection", frame.camera);
+                continue;
+            };
+            let detected = previous.get(&frame.camera)
+                .filter(|(at, _)| at.elapsed() < interval * 5)
+                .and_then(|(_, prev)| detect(prev, &luma, &frame.config, &frame.regions));
+            previous.insert(frame.camera.to_owned(), (Instant::now(), luma));
+            if let Some((regions, ratio)) = detected {
+                movement_detected(&state, &frame.camera, &format!("frame difference {:.1}%", ratio * 100.0), regions).await;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> MotionConfig {
+        MotionConfig { enabled: true, sensitivity: 50, cooldown_secs: 30, max_snapshots: 3, snapshot_interval_secs: 5 }
+    }
+
+    fn region(name: &str, kind: RegionKind, points: &[[f32; 2]]) -> MotionRegion {
+        MotionRegion { name: name.to_string(), kind, points: points.to_vec() }
+    }
+
+    fn frame(changed: impl Fn(usize, usize) -> bool) -> LumaFrame {
+        let (width, height) = (20, 20);
+        let pixels = (0..width * height).map(|i| if changed(i % width, i / width) { 200 } else { 100 }).collect();
+        LumaFrame { width, height, pixels }
+    }
+
+    fn top_left(x: usize, y: usize) -> bool {
+        x < 4 && y < 4
+    }
+
+    #[test]
+    fn contains_square_and_triangle() {
+        let square = region("square", RegionKind::Include, &[[0.25, 0.25], [0.75, 0.25], [0.75, 0.75], [0.25, 0.75]]);
+        assert!(square.contains(0.5, 0.5));
+        assert!(!square.contains(0.1, 0.5));
+        assert!(!square.contains(0.5, 0.9));
+        let triangle = region("triangle", RegionKind::Include, &[[0.0, 0.0], [1.0, 0.0], [0.0, 1.0]]);
+        assert!(triangle.contains(0.2, 0.2));
+        assert!(!triangle.contains(0.8, 0.8));
+    }
+
+    #[test]
+    fn contains_concave_polygon() {
+        let notch = region("notch", RegionKind::Include, &[[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.5, 0.5], [0.0, 1.0]]);
+        assert!(notch.contains(0.5, 0.2));
+        assert!(!notch.contains(0.5, 0.8));
+        assert!(notch.contains(0.9, 0.8));
+    }
+
+    #[test]
+    fn contains_degenerate_polygon() {
+        assert!(!region("empty", RegionKind::Include, &[]).contains(0.5, 0.5));
+        assert!(!region("line", RegionKind::Include, &[[0.0, 0.0], [1.0, 1.0]]).contains(0.5, 0.5));
+    }
+
+    #[test]
+    fn validate_regions_rejects_bad_polygons() {
+        assert!(validate_regions(&[region("a", RegionKind::Include, &[[0.0, 0.0], [1.0, 0.0], [0.0, 1.0]])]).is_ok());
+        assert!(validate_regions(&[region("a", RegionKind::Include, &[])]).is_err());
+        assert!(validate_regions(&[region("a", RegionKind::Include, &[[0.0, 0.0], [2.0, 0.0], [0.0, 1.0]])]).is_err());
+        let triangle = [[0.0, 0.0], [1.0, 0.0], [0.0, 1.0]];
+        assert!(validate_regions(&[region("a", RegionKind::Include, &triangle), region("a", RegionKind::Exclude, &triangle)]).is_err());
+    }
+
+    #[test]
+    fn detect_ignores_static_frames() {
+        assert!(detect(&frame(|_, _| false), &frame(|_, _| false), &config(), &[]).is_none());
+    }
+
+    #[test]
+    fn detect_reports_changed_area() {
+        let (names, ratio) = detect(&frame(|_, _| false), &frame(top_left), &config(), &[]).unwrap();
+        assert!(names.is_empty());
+        assert!((ratio - 16.0 / 400.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn detect_ignores_global_changes_and_size_mismatch() {
+        assert!(detect(&frame(|_, _| false), &frame(|_, _| true), &config(), &[]).is_none());
+        let small = LumaFrame { width: 10, height: 10, pixels: vec![100; 100] };
+        assert!(detect(&small, &frame(top_left), &config(), &[]).is_none());
+    }
+
+    #[test]
+    fn detect_respects_exclude_regions() {
+        let exclude = region("tree", RegionKind::Exclude, &[[0.0, 0.0], [0.5, 0.0], [0.5, 0.5], [0.0, 0.5]]);
+        assert!(detect(&frame(|_, _| false), &frame(top_left), &config(), &[exclude]).is_none());
+    }
+
+    #[test]
+    fn detect_names_include_regions() {
+        let left = region("left", RegionKind::Include, &[[0.0, 0.0], [0.5, 0.0], [0.5, 0.5], [0.0, 0.5]]);
+        let right = region("right", RegionKind::Include, &[[0.5, 0.5], [1.0, 0.5], [1.0, 1.0], [0.5, 1.0]]);
+        let (names, ratio) = detect(&frame(|_, _| false), &frame(top_left), &config(), &[left, right.clone()]).unwrap();
+        assert_eq!(names, vec!["left".to_string()]);
+        assert!((ratio - 16.0 / 100.0).abs() < 1e-9);
+        assert!(detect(&frame(|_, _| false), &frame(top_left), &config(), &[right]).is_none());
+    }
+
+    #[test]
+    fn detect_sensitivity_scales_threshold() {
+        let single = |x: usize, y: usize| x == 0 && y == 0;
+        let low = MotionConfig { sensitivity: 1, ..config() };
+        let high = MotionConfig { sensitivity: 100, ..config() };
+        assert!(detect(&frame(|_, _| false), &frame(single), &low, &[]).is_none());
+        assert!(detect(&frame(|_, _| false), &frame(single), &high, &[]).is_some());
+    }
+
+    #[test]
+    fn thresholds_do_not_overflow_out_of_range_sensitivity() {
+        let over = MotionConfig { sensitivity: 255, ..config() };
+        assert_eq!(over.pixel_threshold(), 10);
+        assert!((over.area_threshold() - 0.002).abs() < 1e-9);
+        assert!(over.validate().is_err());
+    }
+}
@@ -1,16 +1,17 @@
 use std::{env, future::Future, pin::Pin, sync::Arc, time::Duration};
 
 use regex::Regex;
-use rumqttc::{AsyncClient, Event::{Incoming, Outgoing}, EventLoop, MqttOptions, Packet::{Publish, Connect, Disconnect}};
+use rumqttc::{AsyncClient, QoS, Event::{Incoming, Outgoing}, EventLoop, MqttOptions, Packet::{Publish, ConnAck, Disconnect}};
 use serde_json::Value;
-use tokio::{sync::Mutex, task}; 
-use log::info;
+use tokio::{sync::Mutex, task, time}; 
+use log::{info, warn};
 type AsyncFuncType<T> = Box<dyn Fn(T, String, Value) -> Pin<Box<dyn Future<Output=()> + Send>> + Send + Sync>;
 
 pub trait MQTTState {
     fn set_mqtt_client(&self, client: AsyncClient) -> impl Future<Output=()> + Send;
+    fn set_mqtt_connected(&self, connected: bool);
     fn mqtt_client_subscribe(&self, topic: &str) -> impl Future<Output=()> + Send;
-    fn mqtt_publish(&self, topic: &str, body: &str) -> impl Future<Output=()> + Send;
+    fn mqtt_publish(&self, topic: &str, body: &[u8], qos: QoS, retain: bool) -> impl Future<Output=()> + Send;
 }
 
 pub struct Subscription<ST> 
@@ -69,11 +70,7 @@ where
     {
         info!("MQTT Subscribing to {}", &topic);
         let mut subs_locked = self.subs.lock().await;
-        let retext = topic
-            .replace("/", "\\/")
-            .replace("+", "[a-zA-Z0-9_]*")
-            .replace("#", "[a-zA-Z0-9_\\/]*");
-        let regex = Regex::new(retext.as_str()).unwrap();
+        let regex = subscription_regex(topic);
         let subs = Subscription {
             topic: topic.to_string(), 
             regex, 
@@ -106,34 +103,92 @@ where
 
 
 
+fn subscription_regex(topic: &str) -> Regex {
+    let levels: Vec<String> = topic.split('/').map(|level| match level {
+        "+" => "[^/]+".to_string(),
+        "#" => ".*".to_string(),
+        _ => regex::escape(level)
+    }).collect();
+    let retext = levels.join("/");
+    let retext = match retext.strip_suffix("/.*") {
+        Some(prefix) => format!("^{}(/.*)?$", prefix),
+        None => format!("^{}$", retext)
+    };
+    Regex::new(&retext).unwrap()
+}
+
 fn spawn_mqtt_thread<ST>(mut eventloop: EventLoop, mqtt_server: MQTTServer<ST>) 
 where
     ST: MQTTState + Sync + Send + Clone + 'static,
 {
     task::spawn(async move {
         info!("MQTT Server Started");
-        while let Ok(event) = eventloop.poll().await {
+        let mut backoff = Duration::from_secs(1);
+        loop {
+            let event = match eventloop.poll().await {
+                Ok(event) => event,
+                Err(err) => {
+                    warn!("MQTT connection error: {}, retrying in {}s", err, backoff.as_secs());
+                    mqtt_server.state.set_mqtt_connected(false);
+                    time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(Duration::from_secs(30));
+                    continue;
+                }
+            };
             match event {
                 Incoming(packet) => {
                     match packet {
                         Publish(publish) => {
                             let topic = publish.topic.clone();
-                            let body = std::str::from_utf8(&publish.payload).unwrap();
-                            let json_value: Value = serde_json::from_str(body).unwrap();
+                            let body = String::from_utf8_lossy(&publish.payload);
+                            let json_value: Value = serde_json::from_str(&body).unwrap_or(Value::String(body.to_string()));
                             mqtt_server.receive(topic, json_value).await;
                         },
-                        Connect(_connect) => {
+                        ConnAck(_connack) => {
                             info!("MQTT Connected");
+                            backoff = Duration::from_secs(1);
+                            mqtt_server.state.set_mqtt_connected(true);
                             mqtt_server.resubscribe().await;
                         },
                         Disconnect => {
                             info!("MQTT Disconnected");
+                            mqtt_server.state.set_mqtt_connected(false);
                         },
                         _ => {}
                     }
                 },
                 Outgoing(_outgoing) => { },
             }
-        } 
+        }
     });
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_level_wildcard_matches_any_camera_name() {
+        let regex = subscription_regex("home/cams/+/stat");
+        assert!(regex.is_match("home/cams/front-door/stat"));
+        assert!(regex.is_match("home/cams/cam.2/stat"));
+        assert!(!regex.is_match("home/cams/a/b/stat"));
+        assert!(!regex.is_match("home/cams//stat"));
+        assert!(!regex.is_match("prefix/home/cams/a/stat"));
+        assert!(!regex.is_match("home/cams/a/stat/extra"));
+    }
+
+    #[test]
+    fn multi_level_wildcard_matches_parent_and_children() {
+        let regex = subscription_regex("camserver/#");
+        assert!(regex.is_match("camserver"));
+        assert!(regex.is_match("camserver/arm/zone/garden"));
+        assert!(!regex.is_match("camserverx/arm"));
+    }
+
+    #[test]
+    fn literal_levels_are_escaped() {
+        let regex = subscription_regex("home.cams/+/stat");
+        assert!(regex.is_match("home.cams/a/stat"));
+        assert!(!regex.is_match("homexcams/a/stat"));
+    }
+}
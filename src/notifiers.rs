@@ -0,0 +1,567 @@
+use std::{collections::HashMap, env, fs, sync::Arc, time::{Duration, Instant}};
+
+use actix_web::{get, post, web, HttpResponse};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Local, NaiveTime, Utc};
+use lettre::{message::{header::ContentType, Attachment, Mailbox, MultiPart, SinglePart}, transport::smtp::authentication::Credentials, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::{sync::Mutex, task, time};
+use utoipa::ToSchema;
+
+use crate::{error::ApiError, state::AppState};
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Motion,
+    Offline,
+    Online
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+enum SmtpTls {
+    None,
+    #[default]
+    Starttls,
+    Tls
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum NotifierKind {
+    Webhook {
+        url: String,
+        #[serde(default)]
+        headers: HashMap<String, String>,
+        #[serde(default)]
+        include_snapshot: bool
+    },
+    Smtp {
+        host: String,
+        port: Option<u16>,
+        #[serde(default)]
+        tls: SmtpTls,
+        username: Option<String>,
+        password: Option<String>,
+        from: String,
+        to: Vec<String>
+    },
+    Ntfy {
+        url: String,
+        token: Option<String>,
+        #[serde(default)]
+        include_snapshot: bool
+    },
+    Gotify {
+        url: String,
+        token: String,
+        priority: Option<u8>
+    }
+}
+
+impl NotifierKind {
+    fn type_name(&self) -> &'static str {
+        match self {
+            NotifierKind::Webhook { .. } => "webhook",
+            NotifierKind::Smtp { .. } => "smtp",
+            NotifierKind::Ntfy { .. } => "ntfy",
+            NotifierKind::Gotify { .. } => "gotify"
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+struct NotifierConfig {
+    name: String,
+    #[serde(flatten)]
+    kind: NotifierKind
+}
+
+#[derive(Deserialize, Clone)]
+struct QuietHours {
+    start: String,
+    end: String
+}
+
+impl QuietHours {
+    fn bounds(&self) -> Result<(NaiveTime, NaiveTime), String> {
+        let parse = |value: &str| NaiveTime::parse_from_str(value, "%H:%M").map_err(|_| format!("invalid quiet hours time {:?}, expected HH:MM", value));
+        Ok((parse(&self.start)?, parse(&self.end)?))
+    }
+
+    fn contains(&self, now: NaiveTime) -> bool {
+        let Ok((start, end)) = self.bounds() else { return false };
+        if start <= end { now >= start && now < end } else { now >= start || now < end }
+    }
+}
+
+fn all_cameras() -> Vec<String> {
+    vec!["*".to_string()]
+}
+
+fn all_events() -> Vec<EventKind> {
+    vec![EventKind::Motion, EventKind::Offline]
+}
+
+#[derive(Deserialize, Clone)]
+struct RuleConfig {
+    notifiers: Vec<String>,
+    #[serde(default = "all_cameras")]
+    cameras: Vec<String>,
+    #[serde(default = "all_events")]
+    events: Vec<EventKind>,
+    quiet_hours: Option<QuietHours>,
+    #[serde(default)]
+    min_interval_secs: u64
+}
+
+impl RuleConfig {
+    fn matches(&self, notification: &Notification) -> bool {
+        self.events.contains(&notification.kind) && self.cameras.iter().any(|c| c == "*" || *c == notification.camera)
+    }
+}
+
+#[derive(Deserialize, Default)]
+struct NotifyConfig {
+    #[serde(default)]
+    notifiers: Vec<NotifierConfig>,
+    #[serde(default)]
+    rules: Vec<RuleConfig>
+}
+
+impl NotifyConfig {
+    fn validate(&self) -> Result<(), String> {
+        for (index, notifier) in self.notifiers.iter().enumerate() {
+            if self.notifiers[..index].iter().any(|n| n.name == notifier.name) {
+                return Err(format!("duplicate notifier {}", notifier.name));
+            }
+        }
+        for (index, rule) in self.rules.iter().enumerate() {
+            if let Some(unknown) = rule.notifiers.iter().find(|name| self.notifiers.iter().all(|n| n.name != **name)) {
+                return Err(format!("rule {} uses unknown notifier {}", index, unknown));
+            }
+            if let Some(quiet) = &rule.quiet_hours {
+                quiet.bounds().map_err(|err| format!("rule {}: {}", index, err))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct Notification {
+    pub camera: String,
+    pub kind: EventKind,
+    pub timestamp: DateTime<Utc>,
+    pub regions: Vec<String>,
+    pub snapshot: Option<Arc<Vec<u8>>>
+}
+
+impl Notification {
+    fn title(&self) -> String {
+        match self.kind {
+            EventKind::Motion => format!("Motion on {}", self.camera),
+            EventKind::Offline => format!("{} is offline", self.camera),
+            EventKind::Online => format!("{} is online", self.camera)
+        }
+    }
+
+    fn message(&self) -> String {
+        let time = self.timestamp.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S");
+        if self.regions.is_empty() {
+            format!("{} at {}", self.title(), time)
+        } else {
+            format!("{} ({}) at {}", self.title(), self.regions.join(", "), time)
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct NotifierView {
+    name: String,
+    #[serde(rename = "type")]
+    kind: String
+}
+
+pub struct Notifiers {
+    notifiers: Vec<NotifierConfig>,
+    rules: Vec<RuleConfig>,
+    last_sent: Mutex<HashMap<(usize, String), Instant>>,
+    client: reqwest::Client
+}
+
+impl Notifiers {
+    pub fn from_env() -> Self {
+        let config = match env::var("NOTIFY_FILE") {
+            Ok(path) => match fs::read_to_string(&path).map_err(|e| e.to_string()).and_then(|text| serde_json::from_str::<NotifyConfig>(&text).map_err(|e| e.to_string())).and_then(|config| config.validate().map(|_| config)) {
+                Ok(config) => {
+                    info!("Loaded {} notifiers and {} rules from {}", config.notifiers.len(), config.rules.len(), path);
+                    config
+                },
+                Err(err) => {
+                    error!("Could not load notifiers from {}: {}", path, err);
+                    std::process::exit(1);
+                }
+            },
+            Err(_) => NotifyConfig::default()
+        };
+        Notifiers::from_config(config)
+    }
+
+    fn from_config(config: NotifyConfig) -> Self {
+        let client = reqwest::Client::builder().timeout(Duration::from_secs(10)).build().unwrap();
+        Notifiers { notifiers: config.notifiers, rules: config.rules, last_sent: Mutex::new(HashMap::new()), client }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn list(&self) -> Vec<NotifierView> {
+        self.notifiers.iter().map(|n| NotifierView { name: n.name.to_owned(), kind: n.kind.type_name().to_string() }).collect()
+    }
+
+    async fn targets(&self, notification: &Notification, now: NaiveTime) -> Vec<&str> {
+        let mut targets: Vec<&str> = vec![];
+        for (index, rule) in self.rules.iter().enumerate() {
+            if !rule.matches(notification) || rule.quiet_hours.as_ref().is_some_and(|q| q.contains(now)) {
+                continue;
+            }
+            let mut last_sent = self.last_sent.lock().await;
+            let key = (index, notification.camera.to_owned());
+            if last_sent.get(&key).is_some_and(|at| at.elapsed() < Duration::from_secs(rule.min_interval_secs)) {
+                continue;
+            }
+            last_sent.insert(key, Instant::now());
+            targets.extend(rule.notifiers.iter().map(|n| n.as_str()));
+        }
+        targets.sort();
+        targets.dedup();
+        targets
+    }
+
+    pub async fn dispatch(&self, notification: &Notification) {
+        for name in self.targets(notification, Local::now().time()).await {
+            if let Err(err) = self.send(name, notification).await {
+                warn!("Notifier {} failed: {}", name, err);
+            }
+        }
+    }
+
+    pub async fn send(&self, name: &str, notification: &Notification) -> Result<(), ApiError> {
+        let notifier = self.notifiers.iter().find(|n| n.name == name).ok_or(ApiError::NotFound(format!("notifier {}", name)))?;
+        let result = match &notifier.kind {
+            NotifierKind::Webhook { url, headers, include_snapshot } => {
+                let body = json!({
+                    "camera": notification.camera,
+                    "event": notification.kind,
+                    "timestamp": notification.timestamp,
+                    "regions": notification.regions,
+                    "message": notification.message(),
+                    "snapshot": notification.snapshot.as_ref().filter(|_| *include_snapshot).map(|s| STANDARD.encode(s.as_slice()))
+                });
+                let mut request = self.client.post(url).header("Content-Type", "application/json").body(body.to_string());
+                for (key, value) in headers {
+                    request = request.header(key, value);
+                }
+                request.send().await.and_then(|r| r.error_for_status()).map(|_| ()).map_err(|e| e.to_string())
+            },
+            NotifierKind::Ntfy { url, token, include_snapshot } => {
+                let mut request = self.client.post(url).header("Title", notification.title()).header("Tags", "camera");
+                if let Some(token) = token {
+                    request = request.bearer_auth(token);
+                }
+                request = match notification.snapshot.as_ref().filter(|_| *include_snapshot) {
+                    Some(snapshot) => request.header("Message", notification.message()).header("Filename", "snapshot.jpg").body(snapshot.to_vec()),
+                    None => request.body(notification.message())
+                };
+                request.send().await.and_then(|r| r.error_for_status()).map(|_| ()).map_err(|e| e.to_string())
+            },
+            NotifierKind::Gotify { url, token, priority } => {
+                let body = json!({ "title": notification.title(), "message": notification.message(), "priority": priority.unwrap_or(5) });
+                self.client.post(url).header("X-Gotify-Key", token).header("Content-Type", "application/json").body(body.to_string())
+                    .send().await.and_then(|r| r.error_for_status()).map(|_| ()).map_err(|e| e.to_string())
+            },
+            NotifierKind::Smtp { host, port, tls, username, password, from, to } => {
+                send_mail(host, *port, *tls, username, password, from, to, notification).await
+            }
+        };
+        match result {
+            Ok(()) => {
+                info!("Sent {:?} notification for {} via {}", notification.kind, notification.camera, name);
+                Ok(())
+            },
+            Err(err) => Err(ApiError::Internal(err))
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn send_mail(host: &str, port: Option<u16>, tls: SmtpTls, username: &Option<String>, password: &Option<String>, from: &str, to: &[String], notification: &Notification) -> Result<(), String> {
+    let mut builder = match tls {
+        SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+        SmtpTls::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host).map_err(|e| e.to_string())?,
+        SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(host).map_err(|e| e.to_string())?
+    };
+    if let Some(port) = port {
+        builder = builder.port(port);
+    }
+    if let (Some(username), Some(password)) = (username, password) {
+        builder = builder.credentials(Credentials::new(username.to_owned(), password.to_owned()));
+    }
+    let mut message = Message::builder().from(from.parse::<Mailbox>().map_err(|e| e.to_string())?).subject(notification.title());
+    for recipient in to {
+        message = message.to(recipient.parse::<Mailbox>().map_err(|e| e.to_string())?);
+    }
+    let mut body = MultiPart::mixed().singlepart(SinglePart::plain(notification.message()));
+    if let Some(snapshot) = &notification.snapshot {
+        body = body.singlepart(Attachment::new("snapshot.jpg".to_string()).body(snapshot.to_vec(), ContentType::parse("image/jpeg").unwrap()));
+    }
+    let email = message.multipart(body).map_err(|e| e.to_string())?;
+    builder.build().send(email).await.map(|_| ()).map_err(|e| e.to_string())
+}
+
+pub fn notify_presence(state: &AppState, camera: &str, online: bool) {
+    if state.notifiers.is_empty() {
+        return;
+    }
+    let state = state.clone();
+    let notification = Notification {
+        camera: camera.to_string(),
+        kind: if online { EventKind::Online } else { EventKind::Offline },
+        timestamp: Utc::now(),
+        regions: vec![],
+        snapshot: None
+    };
+    task::spawn(async move {
+        state.notifiers.dispatch(&notification).await;
+    });
+}
+
+pub fn notify_motion(state: &AppState, camera: &str, timestamp: DateTime<Utc>, regions: &[String], capturing: bool) {
+    if state.notifiers.is_empty() {
+        return;
+    }
+    let state = state.clone();
+    let camera = camera.to_string();
+    let regions = regions.to_vec();
+    task::spawn(async move {
+        let mut snapshot = None;
+        let attempts = if capturing { 10 } else { 0 };
+        for _ in 0..attempts {
+            time::sleep(Duration::from_millis(500)).await;
+            snapshot = state.for_camera(&camera, |cam| {
+                cam.moves.iter().rev().find(|m| m.timestamp == timestamp).filter(|m| !m.images.is_empty()).map(|_| cam.snapshot.clone())
+            }).await.flatten();
+            if snapshot.is_some() {
+                break;
+            }
+        }
+        state.notifiers.dispatch(&Notification { camera, kind: EventKind::Motion, timestamp, regions, snapshot }).await;
+    });
+}
+
+#[utoipa::path(get, path = "/api/admin/notifiers", responses((status = 200, body = [NotifierView])))]
+#[get("/api/admin/notifiers")]
+async fn get_notifiers(state: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(state.notifiers.list()))
+}
+
+#[utoipa::path(post, path = "/api/admin/notifiers/{name}/test", params(("name" = String, Path)), responses((status = 200), (status = 404), (status = 500)))]
+#[post("/api/admin/notifiers/{name}/test")]
+async fn post_notifier_test(state: web::Data<AppState>, name: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let notification = Notification { camera: "test".to_string(), kind: EventKind::Motion, timestamp: Utc::now(), regions: vec![], snapshot: None };
+    state.notifiers.send(&name, &notification).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader}, net::TcpListener, sync::oneshot};
+
+    use super::*;
+
+    fn notification() -> Notification {
+        Notification { camera: "garden".to_string(), kind: EventKind::Motion, timestamp: Utc::now(), regions: vec!["gate".to_string()], snapshot: Some(Arc::new(vec![0xff, 0xd8, 0xff, 0xd9])) }
+    }
+
+    fn notifiers(config: serde_json::Value) -> Notifiers {
+        Notifiers::from_config(serde_json::from_value(config).unwrap())
+    }
+
+    async fn http_stand_in() -> (String, oneshot::Receiver<(String, Vec<u8>)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (tx, rx) = oneshot::channel();
+        task::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut head = String::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).await.unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                head.push_str(&line);
+            }
+            let length = head.lines()
+                .find_map(|l| l.to_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse::<usize>().unwrap()))
+                .unwrap_or(0);
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).await.unwrap();
+            reader.get_mut().write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\nconnection: close\r\n\r\n").await.unwrap();
+            let _ = tx.send((head, body));
+        });
+        (url, rx)
+    }
+
+    async fn smtp_stand_in() -> (u16, oneshot::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = oneshot::channel();
+        task::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut reader = BufReader::new(stream);
+            reader.get_mut().write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+            let mut transcript = String::new();
+            let mut in_data = false;
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).await.unwrap() == 0 {
+                    break;
+                }
+                transcript.push_str(&line);
+                let reply: &[u8] = if in_data {
+                    if line != ".\r\n" {
+                        continue;
+                    }
+                    in_data = false;
+                    b"250 queued\r\n"
+                } else {
+                    match line.get(..4).unwrap_or_default().to_uppercase().as_str() {
+                        "DATA" => {
+                            in_data = true;
+                            b"354 go ahead\r\n"
+                        },
+                        "QUIT" => {
+                            reader.get_mut().write_all(b"221 bye\r\n").await.unwrap();
+                            break;
+                        },
+                        _ => b"250 OK\r\n"
+                    }
+                };
+                reader.get_mut().write_all(reply).await.unwrap();
+            }
+            let _ = tx.send(transcript);
+        });
+        (port, rx)
+    }
+
+    #[actix_web::test]
+    async fn webhook_posts_json_with_snapshot() {
+        let (url, rx) = http_stand_in().await;
+        let notifiers = notifiers(json!({ "notifiers": [{ "name": "hook", "type": "webhook", "url": url, "headers": { "X-Token": "secret" }, "include_snapshot": true }] }));
+        notifiers.send("hook", &notification()).await.unwrap();
+        let (head, body) = rx.await.unwrap();
+        assert!(head.starts_with("POST /hook "));
+        assert!(head.to_lowercase().contains("x-token: secret"));
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["camera"], "garden");
+        assert_eq!(body["event"], "motion");
+        assert_eq!(body["regions"], json!(["gate"]));
+        assert_eq!(body["snapshot"], STANDARD.encode([0xff, 0xd8, 0xff, 0xd9]));
+    }
+
+    #[actix_web::test]
+    async fn ntfy_posts_message_with_title() {
+        let (url, rx) = http_stand_in().await;
+        let notifiers = notifiers(json!({ "notifiers": [{ "name": "phone", "type": "ntfy", "url": url, "token": "tk" }] }));
+        notifiers.send("phone", &notification()).await.unwrap();
+        let (head, body) = rx.await.unwrap();
+        let head = head.to_lowercase();
+        assert!(head.contains("title: motion on garden"));
+        assert!(head.contains("authorization: bearer tk"));
+        assert!(String::from_utf8(body).unwrap().starts_with("Motion on garden (gate) at "));
+    }
+
+    #[actix_web::test]
+    async fn gotify_posts_priority_and_key() {
+        let (url, rx) = http_stand_in().await;
+        let notifiers = notifiers(json!({ "notifiers": [{ "name": "gotify", "type": "gotify", "url": url, "token": "app", "priority": 8 }] }));
+        notifiers.send("gotify", &notification()).await.unwrap();
+        let (head, body) = rx.await.unwrap();
+        assert!(head.to_lowercase().contains("x-gotify-key: app"));
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["title"], "Motion on garden");
+        assert_eq!(body["priority"], 8);
+    }
+
+    #[actix_web::test]
+    async fn smtp_without_tls_delivers_mail() {
+        let (port, rx) = smtp_stand_in().await;
+        let notifiers = notifiers(json!({ "notifiers": [{ "name": "mail", "type": "smtp", "host": "127.0.0.1", "port": port, "tls": "none", "from": "cam@example.com", "to": ["me@example.com"] }] }));
+        notifiers.send("mail", &notification()).await.unwrap();
+        let transcript = rx.await.unwrap();
+        assert!(transcript.contains("MAIL FROM:<cam@example.com>"));
+        assert!(transcript.contains("RCPT TO:<me@example.com>"));
+        assert!(transcript.contains("Subject: Motion on garden"));
+        assert!(transcript.contains("snapshot.jpg"));
+    }
+
+    #[test]
+    fn quiet_hours_across_midnight() {
+        let quiet = QuietHours { start: "22:00".to_string(), end: "07:00".to_string() };
+        let at = |t: &str| NaiveTime::parse_from_str(t, "%H:%M").unwrap();
+        assert!(quiet.contains(at("22:00")));
+        assert!(quiet.contains(at("23:59")));
+        assert!(quiet.contains(at("00:00")));
+        assert!(quiet.contains(at("06:59")));
+        assert!(!quiet.contains(at("07:00")));
+        assert!(!quiet.contains(at("12:00")));
+        assert!(!quiet.contains(at("21:59")));
+    }
+
+    #[test]
+    fn config_validation_rejects_bad_rules() {
+        let config = |value: serde_json::Value| serde_json::from_value::<NotifyConfig>(value).unwrap().validate();
+        let hook = json!({ "name": "hook", "type": "webhook", "url": "http://localhost/" });
+        assert!(config(json!({ "notifiers": [hook], "rules": [{ "notifiers": ["hook"], "quiet_hours": { "start": "22:00", "end": "07:00" } }] })).is_ok());
+        assert!(config(json!({ "notifiers": [hook], "rules": [{ "notifiers": ["hook"], "quiet_hours": { "start": "10pm", "end": "07:00" } }] })).is_err());
+        assert!(config(json!({ "notifiers": [hook], "rules": [{ "notifiers": ["other"] }] })).is_err());
+        assert!(config(json!({ "notifiers": [hook, hook], "rules": [] })).is_err());
+    }
+
+    #[test]
+    fn quiet_hours_within_day() {
+        let quiet = QuietHours { start: "09:00".to_string(), end: "17:00".to_string() };
+        let at = |t: &str| NaiveTime::parse_from_str(t, "%H:%M").unwrap();
+        assert!(quiet.contains(at("09:00")));
+        assert!(quiet.contains(at("16:59")));
+        assert!(!quiet.contains(at("17:00")));
+        assert!(!quiet.contains(at("08:59")));
+    }
+
+    #[actix_web::test]
+    async fn rules_respect_min_interval_and_quiet_hours() {
+        let notifiers = notifiers(json!({
+            "notifiers": [{ "name": "hook", "type": "webhook", "url": "http://127.0.0.1:9" }, { "name": "night", "type": "webhook", "url": "http://127.0.0.1:9" }],
+            "rules": [
+                { "notifiers": ["hook"], "min_interval_secs": 60 },
+                { "notifiers": ["night"], "quiet_hours": { "start": "08:00", "end": "20:00" } }
+            ]
+        }));
+        let noon = NaiveTime::from_hms_opt(12, 0, 0).unwrap();
+        let midnight = NaiveTime::from_hms_opt(0, 0, 0).unwrap();
+        assert_eq!(notifiers.targets(&notification(), noon).await, vec!["hook"]);
+        assert!(notifiers.targets(&notification(), noon).await.is_empty());
+        assert_eq!(notifiers.targets(&notification(), midnight).await, vec!["night"]);
+        let other = Notification { camera: "porch".to_string(), ..notification() };
+        assert_eq!(notifiers.targets(&other, noon).await, vec!["hook"]);
+        let online = Notification { kind: EventKind::Online, ..notification() };
+        assert!(notifiers.targets(&online, midnight).await.is_empty());
+    }
+}
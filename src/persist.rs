@@ -0,0 +1,98 @@
+use std::{env, fs, io::ErrorKind, process};
+
+use log::{error, info};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{arming::ArmState, commands::StateRequest, daynight::DayNightConfig, motion::{MotionConfig, MotionRegion}, schedule::ScheduleRule};
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CameraConfig {
+    pub name: String,
+    pub stream_id: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+    #[serde(default)]
+    pub disabled: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub zones: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub daynight: Option<DayNightConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub motion: Option<MotionConfig>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub regions: Vec<MotionRegion>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub record: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub settings: Option<StateRequest>
+}
+
+fn camera_file() -> String {
+    env::var("CAMERA_FILE").unwrap_or("cameras.json".to_string())
+}
+
+fn schedule_file() -> String {
+    env::var("SCHEDULE_FILE").unwrap_or("schedules.json".to_string())
+}
+
+fn arming_file() -> String {
+    env::var("ARM_FILE").unwrap_or("arming.json".to_string())
+}
+
+fn load_json<T: DeserializeOwned>(path: &str) -> Option<T> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == ErrorKind::NotFound => return None,
+        Err(err) => {
+            error!("Could not read {}: {}, refusing to start and overwrite it", path, err);
+            process::exit(1);
+        }
+    };
+    match serde_json::from_str::<T>(&text) {
+        Ok(value) => Some(value),
+        Err(err) => {
+            error!("Could not parse {}: {}, fix or remove the file, refusing to start and overwrite it", path, err);
+            process::exit(1);
+        }
+    }
+}
+
+fn save_json<T: Serialize + ?Sized>(path: &str, what: &str, value: &T) {
+    let text = serde_json::to_string_pretty(value).unwrap();
+    let tmp = format!("{}.tmp", path);
+    if let Err(err) = fs::write(&tmp, text).and_then(|_| fs::rename(&tmp, path)) {
+        error!("Could not save {} to {}: {}", what, path, err);
+    }
+}
+
+fn load_list<T: DeserializeOwned>(path: &str, what: &str) -> Vec<T> {
+    let items: Vec<T> = load_json(path).unwrap_or_default();
+    if !items.is_empty() {
+        info!("Loaded {} {} from {}", items.len(), what, path);
+    }
+    items
+}
+
+pub fn load_cameras() -> Vec<CameraConfig> {
+    load_list(&camera_file(), "cameras")
+}
+
+pub fn save_cameras(configs: &[CameraConfig]) {
+    save_json(&camera_file(), "cameras", configs)
+}
+
+pub fn load_schedules() -> Vec<ScheduleRule> {
+    load_list(&schedule_file(), "schedules")
+}
+
+pub fn save_schedules(rules: &[ScheduleRule]) {
+    save_json(&schedule_file(), "schedules", rules)
+}
+
+pub fn load_arming() -> Option<ArmState> {
+    load_json(&arming_file())
+}
+
+pub fn save_arming(state: &ArmState) {
+    save_json(&arming_file(), "arming", state)
+}
@@ -0,0 +1,416 @@
+use std::{collections::HashMap, env, fs::{self, File, OpenOptions}, io::{BufRead, BufReader, ErrorKind, Read, Seek, SeekFrom, Write}, path::{Path, PathBuf}, sync::Arc, time::Duration};
+
+use actix_web::{get, web, web::Bytes, HttpResponse};
+use async_stream::stream;
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::mpsc, task::{self, JoinHandle}, time};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{auth::Identity, error::ApiError, prepare_http_bytes, sessions::{ViewerGuard, FRAME_QUEUE}, state::{AppState, CameraFilter, CameraInfo}, utils::valid_name};
+
+pub struct RecordingSettings {
+    folder: Option<String>,
+    segment_secs: i64,
+    stall_secs: u64,
+    default_enabled: bool
+}
+
+impl RecordingSettings {
+    pub fn from_env() -> Self {
+        RecordingSettings {
+            folder: env::var("RECORD_FOLDER").ok(),
+            segment_secs: env::var("RECORD_SEGMENT_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(600),
+            stall_secs: env::var("RECORD_STALL_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(30),
+            default_enabled: matches!(env::var("RECORD_CONTINUOUS").as_deref(), Ok("true") | Ok("1"))
+        }
+    }
+
+    pub fn enabled_for(&self, cam_info: &CameraInfo) -> bool {
+        self.folder.is_some() && cam_info.record.unwrap_or(self.default_enabled)
+    }
+
+    fn should_record(&self, cam_info: &CameraInfo) -> bool {
+        self.enabled_for(cam_info) && cam_info.online && !cam_info.ip.is_empty()
+    }
+
+    fn camera_folder(&self, cam: &str) -> Result<PathBuf, ApiError> {
+        let folder = self.folder.as_ref().ok_or(ApiError::NotFound("recordings".to_string()))?;
+        if !valid_name(cam) {
+            return Err(ApiError::NotFound(format!("recordings for {}", cam)));
+        }
+        Ok(Path::new(folder).join(cam))
+    }
+}
+
+const MAX_FRAME_BYTES: u64 = 16 * 1024 * 1024;
+
+struct IndexEntry {
+    timestamp: i64,
+    offset: u64,
+    len: u64
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SegmentView {
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    frames: usize,
+    bytes: u64
+}
+
+struct Segment {
+    path: PathBuf,
+    index: Vec<IndexEntry>
+}
+
+impl Segment {
+    fn start(&self) -> i64 {
+        self.index.first().map(|e| e.timestamp).unwrap_or(0)
+    }
+
+    fn end(&self) -> i64 {
+        self.index.last().map(|e| e.timestamp).unwrap_or(0)
+    }
+
+    fn view(&self) -> Option<SegmentView> {
+        Some(SegmentView {
+            start: Utc.timestamp_millis_opt(self.start()).single()?,
+            end: Utc.timestamp_millis_opt(self.end()).single()?,
+            frames: self.index.len(),
+            bytes: self.index.last().map(|e| e.offset + e.len).unwrap_or(0)
+        })
+    }
+
+    fn read_frame(&self, file: &mut File, entry: &IndexEntry) -> std::io::Result<Vec<u8>> {
+        let size = file.metadata()?.len();
+        if entry.len > MAX_FRAME_BYTES || entry.offset.checked_add(entry.len).is_none_or(|end| end > size) {
+            return Err(std::io::Error::new(ErrorKind::InvalidData, format!("index entry outside {}", self.path.display())));
+        }
+        let mut data = vec![0; entry.len as usize];
+        file.seek(SeekFrom::Start(entry.offset))?;
+        file.read_exact(&mut data)?;
+        Ok(data)
+    }
+}
+
+fn read_index(path: &Path) -> Vec<IndexEntry> {
+    let Ok(file) = File::open(path.with_extension("idx")) else { return vec![] };
+    BufReader::new(file).lines().map_while(Result::ok).filter_map(|line| {
+        let mut parts = line.split_whitespace().map(|p| p.parse::<i64>().ok());
+        Some(IndexEntry { timestamp: parts.next()??, offset: parts.next()?? as u64, len: parts.next()?? as u64 })
+    }).collect()
+}
+
+pub fn segment_files(folder: &Path) -> Vec<PathBuf> {
+    let mut files = vec![];
+    let Ok(days) = fs::read_dir(folder) else { return files };
+    for day in days.flatten() {
+        if let Ok(entries) = fs::read_dir(day.path()) {
+            files.extend(entries.flatten().map(|e| e.path()).filter(|p| p.extension().is_some_and(|e| e == "mjpeg")));
+        }
+    }
+    files.sort();
+    files
+}
+
+fn segment_start(path: &Path) -> Option<i64> {
+    let day = path.parent()?.file_name()?.to_str()?;
+    let time = path.file_stem()?.to_str()?;
+    let start = NaiveDateTime::parse_from_str(&format!("{} {}", day, time), "%Y-%m-%d %H-%M-%S").ok()?;
+    Some(start.and_utc().timestamp_millis())
+}
+
+fn candidate_files(folder: &Path, from: i64, to: i64) -> Vec<PathBuf> {
+    let files = segment_files(folder);
+    let starts: Vec<Option<i64>> = files.iter().map(|path| segment_start(path)).collect();
+    files.into_iter().enumerate()
+        .filter(|(i, _)| {
+            let started_after = starts[*i].is_some_and(|start| start > to);
+            let next_started_before = starts.get(i + 1).copied().flatten().is_some_and(|next| next + 1000 < from);
+            !started_after && !next_started_before
+        })
+        .map(|(_, path)| path)
+        .collect()
+}
+
+fn load_segment(path: PathBuf, from: i64, to: i64) -> Option<Segment> {
+    let segment = Segment { index: read_index(&path), path };
+    (!segment.index.is_empty() && segment.end() >= from && segment.start() <= to).then_some(segment)
+}
+
+fn load_segments(folder: &Path, from: i64, to: i64) -> Vec<Segment> {
+    candidate_files(folder, from, to).into_iter().filter_map(|path| load_segment(path, from, to)).collect()
+}
+
+fn find_frame(folder: &Path, at: i64) -> Option<(Segment, usize, Vec<PathBuf>)> {
+    let mut files = candidate_files(folder, at, i64::MAX).into_iter();
+    while let Some(path) = files.next() {
+        let Some(segment) = load_segment(path, at, i64::MAX) else { continue };
+        if let Some(frame) = segment.index.iter().position(|e| e.timestamp >= at) {
+            return Some((segment, frame, files.collect()));
+        }
+    }
+    None
+}
+
+struct SegmentWriter {
+    start: DateTime<Utc>,
+    data: File,
+    index: File,
+    offset: u64
+}
+
+impl SegmentWriter {
+    fn create(folder: &Path, now: DateTime<Utc>) -> std::io::Result<Self> {
+        let day = folder.join(now.format("%Y-%m-%d").to_string());
+        fs::create_dir_all(&day)?;
+        let path = day.join(now.format("%H-%M-%S.mjpeg").to_string());
+        let data = OpenOptions::new().create(true).append(true).open(&path)?;
+        let index = OpenOptions::new().create(true).append(true).open(path.with_extension("idx"))?;
+        let offset = data.metadata()?.len();
+        info!("Recording segment {}", path.display());
+        Ok(SegmentWriter { start: now, data, index, offset })
+    }
+
+    fn write(&mut self, now: DateTime<Utc>, frame: &[u8]) -> std::io::Result<()> {
+        self.data.write_all(frame)?;
+        writeln!(self.index, "{} {} {}", now.timestamp_millis(), self.offset, frame.len())?;
+        self.offset += frame.len() as u64;
+        Ok(())
+    }
+}
+
+async fn record_camera(state: AppState, cam: String) {
+    let Ok(folder) = state.recordings.camera_folder(&cam) else { return };
+    let guard = match ViewerGuard::join(&state, &cam).await {
+        Ok(guard) => guard,
+        Err(err) => {
+            warn!("Could not start recording {}: {}", cam, err);
+            return;
+        }
+    };
+    let (tx, mut rx) = mpsc::channel::<Arc<Vec<u8>>>(FRAME_QUEUE);
+    state.for_mut_camera(&cam, |cam_info| cam_info.add_sender(tx.clone())).await;
+    info!("Recording {}", cam);
+    let _guard = guard;
+    let mut writer: Option<SegmentWriter> = None;
+    let stall = Duration::from_secs(state.recordings.stall_secs.max(1));
+    loop {
+        let frame = match time::timeout(stall, rx.recv()).await {
+            Ok(Some(frame)) => frame,
+            Ok(None) => return,
+            Err(_) => {
+                warn!("No frames from {} for {:?}, restarting recording", cam, stall);
+                state.for_mut_camera(&cam, |cam_info| cam_info.session_since = None).await;
+                return;
+            }
+        };
+        let (folder, cam_name, segment_secs) = (folder.clone(), cam.clone(), state.recordings.segment_secs);
+        writer = task::spawn_blocking(move || write_frame(writer, &folder, &cam_name, segment_secs, &frame)).await.unwrap_or_else(|err| {
+            warn!("Recording task for {} failed: {}", cam, err);
+            None
+        });
+    }
+}
+
+fn write_frame(mut writer: Option<SegmentWriter>, folder: &Path, cam: &str, segment_secs: i64, frame: &[u8]) -> Option<SegmentWriter> {
+    let now = Utc::now();
+    if writer.as_ref().is_none_or(|w| (now - w.start).num_seconds() >= segment_secs) {
+        writer = SegmentWriter::create(folder, now).inspect_err(|err| warn!("Could not create segment for {}: {}", cam, err)).ok();
+    }
+    let mut w = writer?;
+    match w.write(now, frame) {
+        Ok(()) => Some(w),
+        Err(err) => {
+            warn!("Could not write recording for {}: {}", cam, err);
+            None
+        }
+    }
+}
+
+pub fn spawn_recorder(state: AppState) {
+    let Some(root) = state.recordings.folder.clone() else { return };
+    task::spawn(async move {
+        info!("Recorder started in {}", root);
+        let mut running: HashMap<String, JoinHandle<()>> = HashMap::new();
+        let mut interval = time::interval(Duration::from_secs(10));
+        loop {
+            interval.tick().await;
+            let filter = CameraFilter { enabled_only: true, ..CameraFilter::all() };
+            let cameras = state.for_all_cameras(&filter, |cam| (cam.name.to_owned(), cam.zones.clone(), state.recordings.should_record(cam))).await;
+            let mut wanted = vec![];
+            for (name, zones, enabled) in cameras {
+                if enabled && state.arming.actions_for(&zones).await.record {
+                    wanted.push(name);
+                }
+            }
+            running.retain(|name, handle| {
+                let keep = wanted.contains(name) && !handle.is_finished();
+                if !keep {
+                    info!("Stopping recording {}", name);
+                    handle.abort();
+                }
+                keep
+            });
+            for name in wanted {
+                if !running.contains_key(&name) {
+                    running.insert(name.to_owned(), task::spawn(record_camera(state.clone(), name)));
+                }
+            }
+        }
+    });
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct RecordingQuery {
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>
+}
+
+#[utoipa::path(get, path = "/api/{cam}/recordings", params(("cam" = String, Path), RecordingQuery), responses((status = 200, body = [SegmentView]), (status = 404)))]
+#[get("/api/{cam}/recordings")]
+async fn get_recordings(state: web::Data<AppState>, identity: Identity, cam: web::Path<String>, query: web::Query<RecordingQuery>) -> Result<HttpResponse, ApiError> {
+    identity.authorize(&cam)?;
+    let folder = state.recordings.camera_folder(&cam)?;
+    let from = query.from.map(|t| t.timestamp_millis()).unwrap_or(0);
+    let to = query.to.map(|t| t.timestamp_millis()).unwrap_or(i64::MAX);
+    let segments = task::spawn_blocking(move || load_segments(&folder, from, to)).await.map_err(|e| ApiError::Internal(e.to_string()))?;
+    Ok(HttpResponse::Ok().json(segments.iter().filter_map(Segment::view).collect::<Vec<SegmentView>>()))
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct PlaybackQuery {
+    at: DateTime<Utc>,
+    speed: Option<f64>
+}
+
+#[utoipa::path(get, path = "/api/{cam}/recordings/frame", params(("cam" = String, Path), PlaybackQuery), responses((status = 200, content_type = "image/jpeg"), (status = 404)))]
+#[get("/api/{cam}/recordings/frame")]
+async fn get_recording_frame(state: web::Data<AppState>, identity: Identity, cam: web::Path<String>, query: web::Query<PlaybackQuery>) -> Result<HttpResponse, ApiError> {
+    identity.authorize(&cam)?;
+    let folder = state.recordings.camera_folder(&cam)?;
+    let at = query.at.timestamp_millis();
+    let frame = task::spawn_blocking(move || {
+        let (segment, frame, _) = find_frame(&folder, at)?;
+        let mut file = File::open(&segment.path).ok()?;
+        segment.read_frame(&mut file, &segment.index[frame]).ok()
+    }).await.map_err(|e| ApiError::Internal(e.to_string()))?;
+    match frame {
+        Some(data) => Ok(HttpResponse::Ok().content_type("image/jpeg").body(data)),
+        None => Err(ApiError::NotFound(format!("recording of {} at {}", cam, query.at)))
+    }
+}
+
+#[utoipa::path(get, path = "/api/{cam}/recordings/play", params(("cam" = String, Path), PlaybackQuery), responses((status = 200, content_type = "multipart/x-mixed-replace"), (status = 404)))]
+#[get("/api/{cam}/recordings/play")]
+async fn get_recording_play(state: web::Data<AppState>, identity: Identity, cam: web::Path<String>, query: web::Query<PlaybackQuery>) -> Result<HttpResponse, ApiError> {
+    identity.authorize(&cam)?;
+    let folder = state.recordings.camera_folder(&cam)?;
+    let at = query.at.timestamp_millis();
+    let speed = query.speed.unwrap_or(1.0).clamp(0.1, 16.0);
+    let found = task::spawn_blocking(move || find_frame(&folder, at)).await.map_err(|e| ApiError::Internal(e.to_string()))?;
+    let Some((first_segment, first_frame, rest)) = found else {
+        return Err(ApiError::NotFound(format!("recording of {} at {}", cam, query.at)));
+    };
+    Ok(HttpResponse::Ok()
+        .content_type("multipart/x-mixed-replace;boundary=123456789000000000000987654321")
+        .streaming(stream! {
+            let mut last: Option<i64> = None;
+            let mut skip = first_frame;
+            let mut next = Some(first_segment);
+            let mut rest = rest.into_iter();
+            while let Some(segment) = next.take() {
+                next = rest.next().map(|path| Segment { index: read_index(&path), path });
+                let skip = std::mem::take(&mut skip);
+                let Ok(mut file) = File::open(&segment.path) else { continue };
+                for entry in segment.index.iter().skip(skip) {
+                    if let Some(last) = last {
+                        let wait = ((entry.timestamp - last) as f64 / speed).clamp(0.0, 2000.0);
+                        time::sleep(Duration::from_millis(wait as u64)).await;
+                    }
+                    last = Some(entry.timestamp);
+                    match segment.read_frame(&mut file, entry) {
+                        Ok(data) => yield Ok::<Bytes, ApiError>(prepare_http_bytes(Arc::new(data))),
+                        Err(_) => break
+                    }
+                }
+            }
+        })
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_folder() -> PathBuf {
+        let folder = env::temp_dir().join(format!("camserver-rec-{}", uuid::Uuid::new_v4().simple()));
+        fs::create_dir_all(&folder).unwrap();
+        folder
+    }
+
+    fn millis(text: &str) -> i64 {
+        DateTime::parse_from_rfc3339(text).unwrap().timestamp_millis()
+    }
+
+    fn write_segment(folder: &Path, day: &str, time: &str, timestamps: &[i64]) -> PathBuf {
+        let path = folder.join(day).join(format!("{}.mjpeg", time));
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let index: String = timestamps.iter().enumerate().map(|(i, t)| format!("{} {} 4\n", t, i * 4)).collect();
+        fs::write(&path, vec![0u8; timestamps.len() * 4]).unwrap();
+        fs::write(path.with_extension("idx"), index).unwrap();
+        path
+    }
+
+    #[test]
+    fn segment_start_parses_day_and_time() {
+        let start = segment_start(Path::new("/rec/cam/2024-05-01/12-30-15.mjpeg"));
+        assert_eq!(start, Some(millis("2024-05-01T12:30:15Z")));
+        assert_eq!(segment_start(Path::new("/rec/cam/misc/notes.mjpeg")), None);
+    }
+
+    #[test]
+    fn load_segments_skips_segments_outside_the_range() {
+        let folder = temp_folder();
+        write_segment(&folder, "2024-05-01", "10-00-00", &[millis("2024-05-01T10:00:00Z"), millis("2024-05-01T10:09:59Z")]);
+        write_segment(&folder, "2024-05-01", "10-10-00", &[millis("2024-05-01T10:10:00Z"), millis("2024-05-01T10:19:59Z")]);
+        write_segment(&folder, "2024-05-02", "08-00-00", &[millis("2024-05-02T08:00:00Z")]);
+        let segments = load_segments(&folder, millis("2024-05-01T10:15:00Z"), millis("2024-05-01T23:00:00Z"));
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].start(), millis("2024-05-01T10:10:00Z"));
+        assert_eq!(candidate_files(&folder, millis("2024-05-01T10:15:00Z"), i64::MAX).len(), 2);
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn find_frame_returns_first_frame_at_or_after() {
+        let folder = temp_folder();
+        write_segment(&folder, "2024-05-01", "10-00-00", &[millis("2024-05-01T10:00:00Z"), millis("2024-05-01T10:00:01Z")]);
+        write_segment(&folder, "2024-05-01", "10-10-00", &[millis("2024-05-01T10:10:00Z")]);
+        let (segment, frame, rest) = find_frame(&folder, millis("2024-05-01T10:00:00.500Z")).unwrap();
+        assert_eq!(segment.index[frame].timestamp, millis("2024-05-01T10:00:01Z"));
+        assert_eq!(rest.len(), 1);
+        let (segment, frame, rest) = find_frame(&folder, millis("2024-05-01T10:05:00Z")).unwrap();
+        assert_eq!(segment.index[frame].timestamp, millis("2024-05-01T10:10:00Z"));
+        assert!(rest.is_empty());
+        assert!(find_frame(&folder, millis("2024-05-01T11:00:00Z")).is_none());
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn corrupt_index_entries_are_rejected() {
+        let folder = temp_folder();
+        let path = write_segment(&folder, "2024-05-01", "10-00-00", &[millis("2024-05-01T10:00:00Z")]);
+        let segment = Segment { index: vec![], path: path.clone() };
+        let mut file = File::open(&path).unwrap();
+        assert!(segment.read_frame(&mut file, &IndexEntry { timestamp: 0, offset: 0, len: 4 }).is_ok());
+        assert!(segment.read_frame(&mut file, &IndexEntry { timestamp: 0, offset: 2, len: 4 }).is_err());
+        assert!(segment.read_frame(&mut file, &IndexEntry { timestamp: 0, offset: 0, len: u64::MAX }).is_err());
+        assert!(segment.read_frame(&mut file, &IndexEntry { timestamp: 0, offset: u64::MAX, len: 4 }).is_err());
+        let bad = Segment { index: vec![IndexEntry { timestamp: i64::MAX, offset: 0, len: 4 }], path };
+        assert!(bad.view().is_none());
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
@@ -0,0 +1,333 @@
+use std::{env, f64::consts::PI, str::FromStr, time::Duration};
+
+use actix_web::{delete, get, post, put, web, HttpResponse};
+use chrono::{DateTime, Local, NaiveDate, TimeZone, Utc};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::Mutex, task, time};
+use utoipa::ToSchema;
+
+use crate::{auth::Identity, daynight, commands::StateRequest, error::ApiError, persist::{load_schedules, save_schedules}, state::{AppState, CameraFilter}};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduleTarget {
+    Camera(String),
+    Zone(String)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ScheduleTrigger {
+    Cron { expr: String },
+    Sunrise { #[serde(default)] offset_mins: i64 },
+    Sunset { #[serde(default)] offset_mins: i64 }
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+pub struct ScheduleRule {
+    #[serde(default)]
+    pub id: String,
+    pub target: ScheduleTarget,
+    pub trigger: ScheduleTrigger,
+    pub command: StateRequest,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ScheduleView {
+    #[serde(flatten)]
+    pub rule: ScheduleRule,
+    pub next_run: Option<DateTime<Utc>>
+}
+
+#[derive(Clone, Copy)]
+struct Location {
+    lat: f64,
+    lon: f64
+}
+
+pub struct Scheduler {
+    location: Option<Location>,
+    rules: Mutex<Vec<ScheduleRule>>
+}
+
+impl Scheduler {
+    pub fn from_env() -> Self {
+        let lat = env::var("LATITUDE").ok().and_then(|v| v.parse().ok());
+        let lon = env::var("LONGITUDE").ok().and_then(|v| v.parse().ok());
+        let location = match (lat, lon) {
+            (Some(lat), Some(lon)) => Some(Location { lat, lon }),
+            _ => None
+        };
+        Scheduler { location, rules: Mutex::new(load_schedules()) }
+    }
+
+    pub async fn rules(&self) -> Vec<ScheduleView> {
+        let now = Local::now();
+        self.rules.lock().await.iter().map(|rule| ScheduleView {
+            rule: rule.clone(),
+            next_run: if rule.enabled { self.next_run(rule, now).map(|t| t.with_timezone(&Utc)) } else { None }
+        }).collect()
+    }
+
+    pub async fn add(&self, mut rule: ScheduleRule) -> Result<ScheduleRule, ApiError> {
+        self.validate(&rule)?;
+        let mut rules = self.rules.lock().await;
+        if rule.id.trim().is_empty() {
+            rule.id = uuid::Uuid::new_v4().to_string();
+        }
+        if rules.iter().any(|r| r.id == rule.id) {
+            return Err(ApiError::Invalid(format!("schedule {} already exists", rule.id)));
+        }
+        info!("Added schedule {}", rule.id);
+        rules.push(rule.clone());
+        save_schedules(&rules);
+        Ok(rule)
+    }
+
+    pub async fn replace(&self, id: &str, mut rule: ScheduleRule) -> Result<ScheduleRule, ApiError> {
+        self.validate(&rule)?;
+        let mut rules = self.rules.lock().await;
+        let existing = rules.iter_mut().find(|r| r.id == id).ok_or(ApiError::NotFound(format!("schedule {}", id)))?;
+        rule.id = id.to_string();
+        *existing = rule.clone();
+        info!("Updated schedule {}", id);
+        save_schedules(&rules);
+        Ok(rule)
+    }
+
+    pub async fn remove(&self, id: &str) -> Result<(), ApiError> {
+        let mut rules = self.rules.lock().await;
+        let pos = rules.iter().position(|r| r.id == id).ok_or(ApiError::NotFound(format!("schedule {}", id)))?;
+        rules.remove(pos);
+        info!("Removed schedule {}", id);
+        save_schedules(&rules);
+        Ok(())
+    }
+
+    fn validate(&self, rule: &ScheduleRule) -> Result<(), ApiError> {
+        rule.command.validate().map_err(ApiError::Invalid)?;
+        if rule.command.actions().is_some() {
+            return Err(ApiError::Invalid("stream actions cannot be scheduled".to_string()));
+        }
+        match &rule.target {
+            ScheduleTarget::Camera(name) | ScheduleTarget::Zone(name) if name.trim().is_empty() => {
+                return Err(ApiError::Invalid("schedule target must not be empty".to_string()));
+            },
+            _ => {}
+        }
+        match &rule.trigger {
+            ScheduleTrigger::Cron { expr } => {
+                parse_cron(expr)?;
+            },
+            ScheduleTrigger::Sunrise { offset_mins } | ScheduleTrigger::Sunset { offset_mins } => {
+                if self.location.is_none() {
+                    return Err(ApiError::Invalid("LATITUDE and LONGITUDE must be set for sun schedules".to_string()));
+                }
+                if offset_mins.abs() > 720 {
+                    return Err(ApiError::Invalid("offset_mins must be within 12 hours".to_string()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn next_run(&self, rule: &ScheduleRule, after: DateTime<Local>) -> Option<DateTime<Local>> {
+        match &rule.trigger {
+            ScheduleTrigger::Cron { expr } => parse_cron(expr).ok()?.after(&after).next(),
+            ScheduleTrigger::Sunrise { offset_mins } => self.next_sun_event(after, *offset_mins, true),
+            ScheduleTrigger::Sunset { offset_mins } => self.next_sun_event(after, *offset_mins, false)
+        }
+    }
+
+    fn next_sun_event(&self, after: DateTime<Local>, offset_mins: i64, sunrise: bool) -> Option<DateTime<Local>> {
+        let location = self.location?;
+        let today = after.date_naive();
+        (-1..3).filter_map(|days| today.checked_add_signed(chrono::Duration::days(days)))
+            .filter_map(|date| sun_times(date, location.lat, location.lon))
+            .map(|(rise, set)| if sunrise { rise } else { set })
+            .map(|t| t.with_timezone(&Local) + chrono::Duration::minutes(offset_mins))
+            .find(|t| *t > after)
+    }
+
+    async fn due(&self, from: DateTime<Local>, to: DateTime<Local>) -> Vec<ScheduleRule> {
+        self.rules.lock().await.iter()
+            .filter(|rule| rule.enabled)
+            .filter(|rule| self.next_run(rule, from).is_some_and(|t| t <= to))
+            .cloned()
+            .collect()
+    }
+}
+
+fn parse_cron(expr: &str) -> Result<cron::Schedule, ApiError> {
+    let expr = if expr.split_whitespace().count() == 5 { format!("0 {}", expr) } else { expr.to_string() };
+    cron::Schedule::from_str(&expr).map_err(|err| ApiError::Invalid(format!("invalid cron expression: {}", err)))
+}
+
+fn sun_times(date: NaiveDate, lat: f64, lon: f64) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    let rad = PI / 180.0;
+    let days = (date - NaiveDate::from_ymd_opt(2000, 1, 1)?).num_days() as f64 + 0.0008;
+    let mean_noon = days - lon / 360.0;
+    let anomaly = (357.5291 + 0.98560028 * mean_noon).rem_euclid(360.0);
+    let center = 1.9148 * (anomaly * rad).sin() + 0.02 * (2.0 * anomaly * rad).sin() + 0.0003 * (3.0 * anomaly * rad).sin();
+    let ecliptic = (anomaly + center + 180.0 + 102.9372).rem_euclid(360.0);
+    let transit = 2451545.0 + mean_noon + 0.0053 * (anomaly * rad).sin() - 0.0069 * (2.0 * ecliptic * rad).sin();
+    let declination = ((ecliptic * rad).sin() * (23.4397 * rad).sin()).asin();
+    let cos_hour = ((-0.833 * rad).sin() - (lat * rad).sin() * declination.sin()) / ((lat * rad).cos() * declination.cos());
+    if !(-1.0..=1.0).contains(&cos_hour) {
+        return None;
+    }
+    let hour_angle = cos_hour.acos() / rad / 360.0;
+    let to_utc = |julian: f64| Utc.timestamp_millis_opt(((julian - 2440587.5) * 86_400_000.0) as i64).single();
+    Some((to_utc(transit - hour_angle)?, to_utc(transit + hour_angle)?))
+}
+
+async fn run_rule(state: &AppState, rule: &ScheduleRule) {
+    let cameras = match &rule.target {
+        ScheduleTarget::Camera(name) => {
+            if state.for_camera(name, |cam| cam.disabled).await.unwrap_or(false) {
+                info!("Skipping schedule {} on disabled camera {}", rule.id, name);
+                return;
+            }
+            vec![name.to_owned()]
+        }
+        ScheduleTarget::Zone(zone) => {
+            let filter = CameraFilter { enabled_only: true, ..CameraFilter::zone(zone) };
+            state.for_all_cameras(&filter, |cam| cam.name.to_owned()).await
+        }
+    };
+    info!("Running schedule {} on {:?}", rule.id, cameras);
+    for camera in cameras {
+        match state.send_command(&camera, &rule.command).await {
+            Ok(()) => daynight::manual_override(state, &camera, &rule.command).await,
+            Err(err) => warn!("Schedule {} failed for {}: {}", rule.id, camera, err)
+        }
+    }
+}
+
+pub fn spawn_scheduler(state: AppState) {
+    task::spawn(async move {
+        info!("Scheduler started");
+        let mut last = Local::now();
+        let mut interval = time::interval(Duration::from_secs(15));
+        loop {
+            interval.tick().await;
+            let now = Local::now();
+            for rule in state.schedules.due(last, now).await {
+                run_rule(&state, &rule).await;
+            }
+            last = now;
+        }
+    });
+}
+
+#[utoipa::path(get, path = "/api/schedules", responses((status = 200, body = [ScheduleView])))]
+#[get("/api/schedules")]
+async fn get_schedules(state: web::Data<AppState>, identity: Identity) -> Result<HttpResponse, ApiError> {
+    let mut visible = vec![];
+    for view in state.schedules.rules().await {
+        let allowed = match &view.rule.target {
+            ScheduleTarget::Camera(name) => identity.can_view(name),
+            ScheduleTarget::Zone(zone) => state.for_all_cameras(&CameraFilter::zone(zone), |cam| identity.can_view(&cam.name)).await.contains(&true)
+        };
+        if allowed {
+            visible.push(view);
+        }
+    }
+    Ok(HttpResponse::Ok().json(visible))
+}
+
+#[utoipa::path(post, path = "/api/admin/schedules", request_body = ScheduleRule, responses((status = 200, body = ScheduleRule), (status = 422)))]
+#[post("/api/admin/schedules")]
+async fn post_schedule(state: web::Data<AppState>, body: web::Json<ScheduleRule>) -> Result<HttpResponse, ApiError> {
+    let rule = state.schedules.add(body.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(rule))
+}
+
+#[utoipa::path(put, path = "/api/admin/schedules/{id}", params(("id" = String, Path)), request_body = ScheduleRule, responses((status = 200, body = ScheduleRule), (status = 404), (status = 422)))]
+#[put("/api/admin/schedules/{id}")]
+async fn put_schedule(state: web::Data<AppState>, id: web::Path<String>, body: web::Json<ScheduleRule>) -> Result<HttpResponse, ApiError> {
+    let rule = state.schedules.replace(&id, body.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(rule))
+}
+
+#[utoipa::path(delete, path = "/api/admin/schedules/{id}", params(("id" = String, Path)), responses((status = 200), (status = 404)))]
+#[delete("/api/admin/schedules/{id}")]
+async fn delete_schedule(state: web::Data<AppState>, id: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    state.schedules.remove(&id).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Timelike;
+
+    use super::*;
+
+    fn local(y: i32, m: u32, d: u32, h: u32, min: u32, s: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(y, m, d, h, min, s).single().unwrap()
+    }
+
+    fn utc(text: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(text).unwrap().with_timezone(&Utc)
+    }
+
+    fn rule(trigger: ScheduleTrigger, enabled: bool) -> ScheduleRule {
+        ScheduleRule { id: "r".to_string(), target: ScheduleTarget::Camera("a".to_string()), trigger, command: StateRequest { ir: Some(1), ..Default::default() }, enabled }
+    }
+
+    fn cron(expr: &str) -> ScheduleTrigger {
+        ScheduleTrigger::Cron { expr: expr.to_string() }
+    }
+
+    #[test]
+    fn five_field_cron_gets_seconds_prepended() {
+        let next = parse_cron("30 6 * * *").unwrap().after(&local(2024, 5, 1, 0, 0, 0)).next().unwrap();
+        assert_eq!((next.hour(), next.minute(), next.second()), (6, 30, 0));
+        let next = parse_cron("15 30 6 * * *").unwrap().after(&local(2024, 5, 1, 0, 0, 0)).next().unwrap();
+        assert_eq!((next.hour(), next.minute(), next.second()), (6, 30, 15));
+        assert!(parse_cron("15 30 6 * * * 2024").is_ok());
+        assert!(parse_cron("not a cron").is_err());
+    }
+
+    #[test]
+    fn sun_times_match_known_values() {
+        let (rise, set) = sun_times(NaiveDate::from_ymd_opt(2024, 6, 21).unwrap(), 51.5, -0.13).unwrap();
+        assert!((rise - utc("2024-06-21T03:43:00Z")).num_minutes().abs() <= 5, "sunrise {}", rise);
+        assert!((set - utc("2024-06-21T20:21:00Z")).num_minutes().abs() <= 5, "sunset {}", set);
+        let (rise, set) = sun_times(NaiveDate::from_ymd_opt(2024, 3, 20).unwrap(), 0.0, 0.0).unwrap();
+        assert!((set - rise).num_minutes() > 12 * 60 - 10 && (set - rise).num_minutes() < 12 * 60 + 20);
+        assert!(sun_times(NaiveDate::from_ymd_opt(2024, 6, 21).unwrap(), 80.0, 0.0).is_none());
+    }
+
+    #[actix_web::test]
+    async fn due_fires_once_inside_the_window() {
+        let scheduler = Scheduler { location: None, rules: Mutex::new(vec![rule(cron("0 7 * * *"), true)]) };
+        assert_eq!(scheduler.due(local(2024, 5, 1, 6, 59, 50), local(2024, 5, 1, 7, 0, 5)).await.len(), 1);
+        assert!(scheduler.due(local(2024, 5, 1, 7, 0, 5), local(2024, 5, 1, 7, 0, 20)).await.is_empty());
+        assert!(scheduler.due(local(2024, 5, 1, 6, 0, 0), local(2024, 5, 1, 6, 59, 59)).await.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn due_skips_disabled_rules() {
+        let scheduler = Scheduler { location: None, rules: Mutex::new(vec![rule(cron("0 7 * * *"), false)]) };
+        assert!(scheduler.due(local(2024, 5, 1, 6, 59, 50), local(2024, 5, 1, 7, 0, 5)).await.is_empty());
+    }
+
+    #[test]
+    fn sun_events_apply_offset() {
+        let scheduler = Scheduler { location: Some(Location { lat: 51.5, lon: -0.13 }), rules: Mutex::new(vec![]) };
+        let (rise, set) = sun_times(NaiveDate::from_ymd_opt(2024, 6, 21).unwrap(), 51.5, -0.13).unwrap();
+        let after = (rise - chrono::Duration::hours(2)).with_timezone(&Local);
+        let next = scheduler.next_run(&rule(ScheduleTrigger::Sunrise { offset_mins: -30 }, true), after).unwrap();
+        assert_eq!(next, (rise - chrono::Duration::minutes(30)).with_timezone(&Local));
+        let next = scheduler.next_run(&rule(ScheduleTrigger::Sunset { offset_mins: 15 }, true), after).unwrap();
+        assert_eq!(next, (set + chrono::Duration::minutes(15)).with_timezone(&Local));
+    }
+}
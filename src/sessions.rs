@@ -0,0 +1,95 @@
+use std::{env, time::{Duration, SystemTime}};
+
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use serde::Serialize;
+use tokio::{task, time};
+use utoipa::ToSchema;
+
+use crate::{commands::StateRequest, error::ApiError, state::AppState};
+
+pub const FRAME_QUEUE: usize = 8;
+
+#[derive(Serialize, ToSchema)]
+pub struct StreamSessionInfo {
+    pub camera: String,
+    pub viewers: u32,
+    pub since: DateTime<Utc>
+}
+
+pub struct ViewerGuard {
+    state: AppState,
+    cam: String
+}
+
+impl ViewerGuard {
+    pub async fn join(state: &AppState, cam: &str) -> Result<ViewerGuard, ApiError> {
+        let start_lock = state.for_camera(cam, |cam_info| cam_info.start_lock.clone()).await.ok_or(ApiError::NotFound(format!("camera {}", cam)))?;
+        let _starting = start_lock.lock().await;
+        let now: DateTime<Utc> = SystemTime::now().into();
+        let start = state.for_mut_camera(cam, |cam_info| {
+            if cam_info.disabled {
+                return Err(ApiError::Invalid(format!("camera {} is disabled", cam_info.name)));
+            }
+            cam_info.viewers += 1;
+            cam_info.viewer_epoch += 1;
+            if cam_info.session_since.is_none() {
+                cam_info.session_since = Some(now);
+                Ok(Some((cam_info.stream_id, cam_info.ip.to_owned())))
+            } else {
+                Ok(None)
+            }
+        }).await.ok_or(ApiError::NotFound(format!("camera {}", cam)))??;
+        if let Some((stream_id, cam_ip)) = start {
+            if let Err(err) = start_stream(state, cam, stream_id, &cam_ip).await {
+                warn!("Could not start stream for {}: {}", cam, err);
+                state.for_mut_camera(cam, |cam_info| {
+                    cam_info.viewers = cam_info.viewers.saturating_sub(1);
+                    if cam_info.session_since == Some(now) {
+                        cam_info.session_since = None;
+                    }
+                }).await;
+                return Err(err);
+            }
+        }
+        Ok(ViewerGuard { state: state.clone(), cam: cam.to_string() })
+    }
+}
+
+async fn start_stream(state: &AppState, cam: &str, stream_id: u8, cam_ip: &str) -> Result<(), ApiError> {
+    let target = state.stream_target_ip(cam_ip).ok_or(ApiError::Internal(format!("no local address found to stream {} at {}", cam, cam_ip)))?;
+    info!("Starting stream session for {} to {}", cam, target);
+    let request = StateRequest { streamto: Some(target), streamid: Some(stream_id), ..Default::default() };
+    state.send_command(cam, &request).await
+}
+
+impl Drop for ViewerGuard {
+    fn drop(&mut self) {
+        task::spawn(leave(self.state.clone(), self.cam.to_owned()));
+    }
+}
+
+async fn leave(state: AppState, cam: String) {
+    let epoch = state.for_mut_camera(&cam, |cam_info| {
+        cam_info.viewers = cam_info.viewers.saturating_sub(1);
+        if cam_info.viewers == 0 { Some(cam_info.viewer_epoch) } else { None }
+    }).await.flatten();
+    let Some(epoch) = epoch else { return };
+    let grace = env::var("STREAM_GRACE_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(10);
+    time::sleep(Duration::from_secs(grace)).await;
+    let stop = state.for_mut_camera(&cam, |cam_info| {
+        if cam_info.viewers == 0 && cam_info.viewer_epoch == epoch && cam_info.session_since.is_some() {
+            cam_info.session_since = None;
+            true
+        } else {
+            false
+        }
+    }).await.unwrap_or(false);
+    if stop {
+        info!("Stopping stream session for {}", cam);
+        let request = StateRequest { streamto: Some("".to_string()), streamid: Some(0), ..Default::default() };
+        if let Err(err) = state.send_command(&cam, &request).await {
+            warn!("Could not stop stream for {}: {}", cam, err);
+        }
+    }
+}
@@ -1,31 +1,165 @@
-use std::{sync::{mpsc::Sender, Arc}, time::{SystemTime, UNIX_EPOCH}};
+use std::{env, sync::{atomic::{AtomicBool, Ordering}, Arc}, time::{SystemTime, UNIX_EPOCH}};
 
 use chrono::{DateTime, Utc};
-use log::info;
+use log::{info, warn};
 use rumqttc::{AsyncClient, QoS};
-use tokio::sync::Mutex;
-use crate::{mqtt::MQTTState, stream::StreamReceiverState};
+use serde_json::Value;
+use tokio::{sync::{broadcast, mpsc::{error::TrySendError, Sender}, Mutex}, task};
+use uuid::Uuid;
+use crate::{admin::CameraUpdate, arming::Arming, auth::Auth, commands::{CommandPolicies, StateRequest}, daynight::{DayNightConfig, DayNightState}, error::ApiError, events::{EventPublisher, UiEvent}, homeassistant::HomeAssistant, motion::{self, validate_regions, MotionConfig, MotionDetector, MotionRegion}, mqtt::MQTTState, notifiers::{notify_presence, Notifiers}, persist::{load_cameras, save_cameras, CameraConfig}, recording::RecordingSettings, schedule::Scheduler, sessions::StreamSessionInfo, storage::Storage, stream::StreamReceiverState, topics::Topics, utils::{local_ip_towards, valid_name}};
 
 
+#[derive(Default)]
+pub struct CameraFilter {
+    pub zone: Option<String>,
+    pub enabled_only: bool
+}
+
+impl CameraFilter {
+    pub fn all() -> Self {
+        CameraFilter::default()
+    }
+
+    pub fn zone(zone: &str) -> Self {
+        CameraFilter { zone: Some(zone.trim().to_lowercase()), enabled_only: false }
+    }
+
+    fn matches(&self, cam_info: &CameraInfo) -> bool {
+        if self.enabled_only && cam_info.disabled {
+            return false;
+        }
+        match &self.zone {
+            Some(zone) => cam_info.zones.iter().any(|z| z == zone),
+            None => true
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Movement {
+    pub id: String,
+    pub timestamp: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub count: u32,
+    pub images: Vec<String>,
+    pub regions: Vec<String>,
+    snapshots: u32,
+    last_snapshot: DateTime<Utc>
+}
+
+pub struct MovementUpdate {
+    pub id: String,
+    pub timestamp: DateTime<Utc>,
+    pub started: bool,
+    pub snapshot: bool
+}
+
 #[derive(Clone)]
 pub struct CameraInfo {
     pub name: String,
+    pub alias: Option<String>,
+    pub disabled: bool,
+    pub zones: Vec<String>,
+    pub daynight: Option<DayNightConfig>,
+    pub daynight_state: DayNightState,
+    pub motion: Option<MotionConfig>,
+    pub regions: Vec<MotionRegion>,
+    pub record: Option<bool>,
     pub ip: String,
     pub stream_id: u8,
     pub lum: u8,
-    pub moves: Vec<DateTime<Utc>>,
+    pub online: bool,
+    pub last_stat: DateTime<Utc>,
+    pub telemetry: Value,
+    pub moves: Vec<Movement>,
     pub image: Arc<Vec<u8>>,
     pub last_image: u32,
-    pub senders: Vec<Sender<Arc<Vec<u8>>>>
+    pub last_frame: Option<DateTime<Utc>>,
+    pub frame_id: u32,
+    pub stream_started: Option<DateTime<Utc>>,
+    pub stream_frames: u64,
+    pub stream_bytes: u64,
+    pub snapshot: Arc<Vec<u8>>,
+    pub settings: StateRequest,
+    pub viewers: u32,
+    pub viewer_epoch: u64,
+    pub session_since: Option<DateTime<Utc>>,
+    pub senders: Vec<Sender<Arc<Vec<u8>>>>,
+    pub start_lock: Arc<Mutex<()>>
 }
 
 impl CameraInfo {
     pub fn new(name: &str, stream_id: u8) -> Self {
-        CameraInfo { name: name.to_string(), ip: "".to_string(), stream_id: stream_id,  lum: 0, moves: vec![], image: Arc::new(vec![]), last_image: 0, senders: vec![] }
+        CameraInfo { name: name.to_string(), alias: None, disabled: false, zones: vec![], daynight: None, daynight_state: DayNightState::default(), motion: None, regions: vec![], record: None, ip: "".to_string(), stream_id: stream_id,  lum: 0, online: false, last_stat: SystemTime::now().into(), telemetry: Value::Null, moves: vec![], image: Arc::new(vec![]), last_image: 0, last_frame: None, frame_id: 0, stream_started: None, stream_frames: 0, stream_bytes: 0, snapshot: Arc::new(vec![]), settings: StateRequest::default(), viewers: 0, viewer_epoch: 0, session_since: None, senders: vec![], start_lock: Arc::new(Mutex::new(())) }
+    }
+
+    pub fn from_config(config: &CameraConfig) -> Self {
+        let mut cam_info = CameraInfo::new(&config.name, config.stream_id);
+        cam_info.alias = config.alias.clone();
+        cam_info.disabled = config.disabled;
+        cam_info.zones = config.zones.iter().filter(|zone| {
+            let valid = valid_name(zone);
+            if !valid {
+                warn!("Ignoring zone {:?} of {}: invalid name", zone, config.name);
+            }
+            valid
+        }).cloned().collect();
+        cam_info.daynight = config.daynight.clone();
+        cam_info.motion = config.motion.clone().filter(|motion| {
+            motion.validate().inspect_err(|err| warn!("Ignoring motion config of {}: {}", config.name, err)).is_ok()
+        });
+        cam_info.regions = match validate_regions(&config.regions) {
+            Ok(()) => config.regions.clone(),
+            Err(err) => {
+                warn!("Ignoring motion regions of {}: {}", config.name, err);
+                vec![]
+            }
+        };
+        cam_info.record = config.record;
+        if let Some(settings) = config.settings.as_ref().and_then(StateRequest::settings) {
+            match settings.validate() {
+                Ok(()) => cam_info.settings = settings,
+                Err(err) => warn!("Ignoring saved settings of {}: {}", config.name, err)
+            }
+        }
+        cam_info
+    }
+
+    pub fn config(&self) -> CameraConfig {
+        CameraConfig { name: self.name.to_owned(), stream_id: self.stream_id, alias: self.alias.clone(), disabled: self.disabled, zones: self.zones.clone(), daynight: self.daynight.clone(), motion: self.motion.clone(), regions: self.regions.clone(), record: self.record, settings: self.settings.settings() }
     }
 
-    pub fn record_movement(&mut self) {
-        self.moves.push(SystemTime::now().into());
+    pub fn display_name(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.name)
+    }
+
+    pub fn record_movement(&mut self, regions: &[String], config: &MotionConfig) -> MovementUpdate {
+        let now: DateTime<Utc> = SystemTime::now().into();
+        if let Some(mvt) = self.moves.last_mut().filter(|m| (now - m.end).num_seconds() < config.cooldown_secs) {
+            mvt.end = now;
+            mvt.count += 1;
+            for region in regions {
+                if !mvt.regions.contains(region) {
+                    mvt.regions.push(region.to_owned());
+                }
+            }
+            let snapshot = mvt.snapshots < config.max_snapshots && (now - mvt.last_snapshot).num_seconds() >= config.snapshot_interval_secs;
+            if snapshot {
+                mvt.snapshots += 1;
+                mvt.last_snapshot = now;
+            }
+            return MovementUpdate { id: mvt.id.to_owned(), timestamp: mvt.timestamp, started: false, snapshot };
+        }
+        let snapshot = config.max_snapshots > 0;
+        let id = Uuid::new_v4().to_string();
+        self.moves.push(Movement { id: id.to_owned(), timestamp: now, end: now, count: 1, images: vec![], regions: regions.to_vec(), snapshots: snapshot as u32, last_snapshot: now });
+        MovementUpdate { id, timestamp: now, started: true, snapshot }
+    }
+
+    pub fn add_movement_image(&mut self, timestamp: DateTime<Utc>, image: &str) {
+        if let Some(mvt) = self.moves.iter_mut().rev().find(|m| m.timestamp == timestamp) {
+            mvt.images.push(image.to_string());
+        }
     }
 
     pub fn add_sender(&mut self, sender: Sender<Arc<Vec<u8>>>) {
@@ -38,12 +172,27 @@ pub struct CamerasState {
 }
 
 impl CamerasState {
-    pub fn new() -> Self {
-        CamerasState { cameras: vec![] }
+    pub fn from_configs(configs: &[CameraConfig]) -> Self {
+        let valid = configs.iter().filter(|config| {
+            let valid = valid_name(&config.name);
+            if !valid {
+                warn!("Ignoring camera {:?} in the camera file: invalid name", config.name);
+            }
+            valid
+        });
+        CamerasState { cameras: valid.map(CameraInfo::from_config).collect() }
+    }
+
+    fn configs(&self) -> Vec<CameraConfig> {
+        self.cameras.iter().map(|cam_info| cam_info.config()).collect()
+    }
+
+    fn next_stream_id(&self) -> u8 {
+        (1..=u8::MAX).find(|id| self.cameras.iter().all(|cam_info| cam_info.stream_id != *id)).unwrap_or(0)
     }
 
     fn add_camera(&mut self, name: &str) -> &mut CameraInfo {
-        let new_stream_id = (self.cameras.len() + 1) as u8;
+        let new_stream_id = self.next_stream_id();
         let new_cam_info = CameraInfo::new(name, new_stream_id);
         self.cameras.push(new_cam_info);
         self.get_mut_camera_from_name(name).unwrap()
@@ -64,6 +213,15 @@ impl CamerasState {
         ret
     }
 
+    fn remove_camera(&mut self, name: &str) -> Option<CameraInfo> {
+        let pos = self.cameras.iter().position(|cam_info| cam_info.name == name)?;
+        Some(self.cameras.remove(pos))
+    }
+
+    fn get_all_mut_cameras(&mut self) -> Vec<&mut CameraInfo> {
+        self.cameras.iter_mut().collect()
+    }
+
     fn get_all_cameras(&self) -> Vec<&CameraInfo> {
         let mut ret: Vec<&CameraInfo> = self.cameras.iter().map(|ci| ci).collect();
         ret.sort_by(|a, b| a.name.cmp(&b.name));
@@ -76,19 +234,73 @@ impl CamerasState {
 #[derive(Clone)]
 pub struct AppState {
     mqttclient: Arc<Mutex<Option<AsyncClient>>>,
-    cameras: Arc<Mutex<CamerasState>>
+    mqtt_connected: Arc<AtomicBool>,
+    cameras: Arc<Mutex<CamerasState>>,
+    homeassistant: Arc<Option<HomeAssistant>>,
+    pub events: Arc<EventPublisher>,
+    pub topics: Arc<Topics>,
+    commands: Arc<CommandPolicies>,
+    pub auth: Arc<Auth>,
+    pub schedules: Arc<Scheduler>,
+    pub motion: Arc<MotionDetector>,
+    pub notifiers: Arc<Notifiers>,
+    pub arming: Arc<Arming>,
+    pub recordings: Arc<RecordingSettings>,
+    pub storage: Arc<Storage>,
+    ui_events: broadcast::Sender<UiEvent>
 }
 
 impl AppState {
     pub fn new() -> Self {
+        let topics = Topics::from_env();
         Self { 
             mqttclient: Arc::new(Mutex::new(None)),
-            cameras: Arc::new(Mutex::new(CamerasState::new()))
+            mqtt_connected: Arc::new(AtomicBool::new(false)),
+            cameras: Arc::new(Mutex::new(CamerasState::from_configs(&load_cameras()))),
+            homeassistant: Arc::new(HomeAssistant::from_env(topics.clone())),
+            events: Arc::new(EventPublisher::from_env(topics.clone())),
+            topics: Arc::new(topics),
+            commands: Arc::new(CommandPolicies::from_env()),
+            auth: Arc::new(Auth::from_env()),
+            schedules: Arc::new(Scheduler::from_env()),
+            motion: Arc::new(MotionDetector::new()),
+            notifiers: Arc::new(Notifiers::from_env()),
+            arming: Arc::new(Arming::from_env()),
+            recordings: Arc::new(RecordingSettings::from_env()),
+            storage: Arc::new(Storage::from_env()),
+            ui_events: broadcast::channel(100).0
+        }
+    }
+
+    pub fn stream_target_ip(&self, cam_ip: &str) -> Option<String> {
+        if let Ok(ip) = env::var("UDP_STREAM_IP") {
+            return Some(ip);
         }
+        local_ip_towards(cam_ip).map(|ip| ip.to_string())
     }
 
-    pub async fn set_camera_stat(&self, name: &str, ip: &str, lum: u8) {
+    pub async fn stream_sessions(&self) -> Vec<StreamSessionInfo> {
+        let sessions = self.for_all_cameras(&CameraFilter::all(), |cam| {
+            cam.session_since.map(|since| StreamSessionInfo { camera: cam.name.to_owned(), viewers: cam.viewers, since })
+        }).await;
+        sessions.into_iter().flatten().collect()
+    }
+
+    pub fn notify(&self, event: UiEvent) {
+        let _ = self.ui_events.send(event);
+    }
+
+    pub fn subscribe_ui_events(&self) -> broadcast::Receiver<UiEvent> {
+        self.ui_events.subscribe()
+    }
+
+    pub async fn set_camera_stat(&self, name: &str, ip: &str, lum: u8, telemetry: Value) {
         let mut lock = self.cameras.lock().await;
+        let is_new = lock.get_camera_from_name(name).is_none();
+        if is_new && !valid_name(name) {
+            warn!("Ignoring camera {:?}: names may only contain letters, digits, '-', '_' and '.'", name);
+            return;
+        }
         //if let Ok(mut lock) = self.cameras.lock().await {
             let cam_info = match lock.get_mut_camera_from_name(name) {
                 Some(cam_info) => cam_info,
@@ -96,7 +308,173 @@ impl AppState {
             };
             cam_info.ip = ip.to_string();
             cam_info.lum = lum;
+            cam_info.telemetry = telemetry;
+            cam_info.last_stat = SystemTime::now().into();
+            let came_online = !cam_info.online;
+            cam_info.online = true;
+            let stream_id = cam_info.stream_id;
         //};
+        let configs = if is_new { Some(lock.configs()) } else { None };
+        drop(lock);
+        if let Some(configs) = configs {
+            save_cameras(&configs);
+        }
+        if is_new {
+            self.notify(UiEvent::CameraAdded { camera: name.to_string(), ip: ip.to_string(), stream_id });
+            if let Some(ha) = self.homeassistant.as_ref() {
+                ha.publish_camera(self, name).await;
+            }
+        }
+        self.notify(UiEvent::Stat { camera: name.to_string(), ip: ip.to_string(), lum });
+        if came_online {
+            info!("Cam {} online", name);
+            self.notify(UiEvent::Online { camera: name.to_string(), online: true });
+            self.events.publish_online(self, name, true).await;
+            notify_presence(self, name, true);
+        }
+    }
+
+    pub async fn check_presence(&self, timeout: chrono::Duration) {
+        let now: DateTime<Utc> = SystemTime::now().into();
+        let mut lock = self.cameras.lock().await;
+        let mut gone: Vec<String> = vec![];
+        for cam_info in lock.get_all_mut_cameras() {
+            if cam_info.online && now - cam_info.last_stat > timeout {
+                cam_info.online = false;
+                gone.push(cam_info.name.to_owned());
+            }
+        }
+        drop(lock);
+        for name in gone.iter() {
+            info!("Cam {} offline", name);
+            self.notify(UiEvent::Online { camera: name.to_string(), online: false });
+            self.events.publish_online(self, name, false).await;
+            notify_presence(self, name, false);
+        }
+    }
+
+    pub fn mqtt_connected(&self) -> bool {
+        self.mqtt_connected.load(Ordering::Relaxed)
+    }
+
+    pub async fn send_command(&self, name: &str, request: &StateRequest) -> Result<(), ApiError> {
+        if !self.mqtt_connected() {
+            return Err(ApiError::MqttUnavailable);
+        }
+        let topic = self.topics.cmd.topic(name);
+        if let Some(settings) = request.settings() {
+            let merged = self.for_mut_camera(name, |cam| {
+                cam.settings.merge(&settings);
+                cam.settings.clone()
+            }).await.ok_or(ApiError::NotFound(format!("camera {}", name)))?;
+            let body = serde_json::to_vec(&merged)?;
+            self.mqtt_publish(&topic, &body, self.commands.setting.qos, self.commands.setting.retain).await;
+            self.save_cameras().await;
+        }
+        if let Some(actions) = request.actions() {
+            let body = serde_json::to_vec(&actions)?;
+            self.mqtt_publish(&topic, &body, self.commands.action.qos, self.commands.action.retain).await;
+        }
+        Ok(())
+    }
+
+    pub async fn clear_retained_commands(&self, name: &str) -> Result<(), ApiError> {
+        if !self.mqtt_connected() {
+            return Err(ApiError::MqttUnavailable);
+        }
+        self.for_mut_camera(name, |cam| {
+            cam.settings = StateRequest::default();
+        }).await.ok_or(ApiError::NotFound(format!("camera {}", name)))?;
+        self.save_cameras().await;
+        self.mqtt_publish(&self.topics.cmd.topic(name), &[], self.commands.setting.qos, true).await;
+        Ok(())
+    }
+
+    async fn save_cameras(&self) {
+        let configs = self.cameras.lock().await.configs();
+        save_cameras(&configs);
+    }
+
+    pub async fn remove_camera(&self, name: &str) -> Result<(), ApiError> {
+        let streaming = self.for_camera(name, |cam| cam.session_since.is_some()).await.ok_or(ApiError::NotFound(format!("camera {}", name)))?;
+        if streaming {
+            info!("Stopping stream session for removed camera {}", name);
+            let request = StateRequest { streamto: Some("".to_string()), streamid: Some(0), ..Default::default() };
+            if let Err(err) = self.send_command(name, &request).await {
+                warn!("Could not stop stream for {}: {}", name, err);
+            }
+        }
+        let removed = self.cameras.lock().await.remove_camera(name);
+        if removed.is_none() {
+            return Err(ApiError::NotFound(format!("camera {}", name)));
+        }
+        info!("Removed camera {}, it will be added again if it reports a stat message", name);
+        self.save_cameras().await;
+        self.mqtt_publish(&self.topics.cmd.topic(name), &[], self.commands.setting.qos, true).await;
+        if let Some(ha) = self.homeassistant.as_ref() {
+            ha.remove_camera(self, name).await;
+        }
+        Ok(())
+    }
+
+    pub async fn update_camera(&self, name: &str, update: CameraUpdate) -> Result<(), ApiError> {
+        let CameraUpdate { alias, disabled, stream_id, zones, daynight, motion, regions, record } = update;
+        if let Some(daynight) = &daynight {
+            daynight.validate().map_err(ApiError::Invalid)?;
+        }
+        if let Some(motion) = &motion {
+            motion.validate().map_err(ApiError::Invalid)?;
+        }
+        if let Some(regions) = &regions {
+            validate_regions(regions).map_err(ApiError::Invalid)?;
+        }
+        let zones: Option<Vec<String>> = zones.map(|zones| zones.iter().map(|z| z.trim().to_lowercase()).filter(|z| !z.is_empty()).collect());
+        if let Some(invalid) = zones.iter().flatten().find(|zone| !valid_name(zone)) {
+            return Err(ApiError::BadRequest(format!("invalid zone name {:?}: zones may only contain letters, digits, '-', '_' and '.'", invalid)));
+        }
+        let mut lock = self.cameras.lock().await;
+        if let Some(id) = stream_id {
+            if id == 0 {
+                return Err(ApiError::Invalid("stream id must be between 1 and 255".to_string()));
+            }
+            if lock.cameras.iter().any(|cam_info| cam_info.stream_id == id && cam_info.name != name) {
+                return Err(ApiError::Invalid(format!("stream id {} is already in use", id)));
+            }
+        }
+        let cam_info = lock.get_mut_camera_from_name(name).ok_or(ApiError::NotFound(format!("camera {}", name)))?;
+        if stream_id.is_some() && cam_info.session_since.is_some() {
+            return Err(ApiError::Invalid("cannot change the stream id while the camera is streaming".to_string()));
+        }
+        if let Some(alias) = alias {
+            cam_info.alias = if alias.trim().is_empty() { None } else { Some(alias.trim().to_string()) };
+        }
+        if let Some(disabled) = disabled {
+            cam_info.disabled = disabled;
+        }
+        if let Some(id) = stream_id {
+            cam_info.stream_id = id;
+        }
+        if let Some(zones) = zones {
+            cam_info.zones = zones;
+        }
+        if let Some(daynight) = daynight {
+            cam_info.daynight = Some(daynight);
+            cam_info.daynight_state = DayNightState::default();
+        }
+        if let Some(motion) = motion {
+            cam_info.motion = Some(motion);
+        }
+        if let Some(regions) = regions {
+            cam_info.regions = regions;
+        }
+        if let Some(record) = record {
+            cam_info.record = Some(record);
+        }
+        info!("Updated camera {}", name);
+        let configs = lock.configs();
+        drop(lock);
+        save_cameras(&configs);
+        Ok(())
     }
 
     pub async fn for_camera<FT, RT>(&self, name: &str, func: FT) -> Option<RT>
@@ -119,11 +497,18 @@ impl AppState {
         None
     }
 
-    pub async fn for_all_cameras<FT, RT>(&self, func: FT) -> Vec<RT>
+    pub async fn for_all_cameras<FT, RT>(&self, filter: &CameraFilter, func: FT) -> Vec<RT>
     where FT: Fn(&CameraInfo) -> RT {
         let lock = self.cameras.lock().await;
         let all_cams = lock.get_all_cameras();
-        all_cams.iter().map(|cam_info| func(&cam_info)).collect()
+        all_cams.iter().filter(|cam_info| filter.matches(cam_info)).map(|cam_info| func(cam_info)).collect()
+    }
+
+    pub async fn zones(&self) -> Vec<String> {
+        let mut zones: Vec<String> = self.for_all_cameras(&CameraFilter::all(), |cam| cam.zones.clone()).await.into_iter().flatten().collect();
+        zones.sort();
+        zones.dedup();
+        zones
     }
 }
 
@@ -135,6 +520,19 @@ impl MQTTState for AppState {
         *client_option = Some(client);
     }
 
+    fn set_mqtt_connected(&self, connected: bool) {
+        let was_connected = self.mqtt_connected.swap(connected, Ordering::Relaxed);
+        if connected && !was_connected && self.homeassistant.is_some() {
+            let state = self.clone();
+            task::spawn(async move {
+                let Some(ha) = state.homeassistant.as_ref() else { return };
+                for cam in state.for_all_cameras(&CameraFilter::all(), |cam| cam.name.to_owned()).await {
+                    ha.publish_camera(&state, &cam).await;
+                }
+            });
+        }
+    }
+
     async fn mqtt_client_subscribe(&self, topic: &str) {
         let client_option = self.mqttclient.lock().await;
         if let Some(client) = client_option.as_ref() {
@@ -142,11 +540,14 @@ impl MQTTState for AppState {
         }
     }
 
-    async fn mqtt_publish(&self, topic: &str, body: &str) {
-        info!("MQTT publish {} {}", topic, body);
+    async fn mqtt_publish(&self, topic: &str, body: &[u8], qos: QoS, retain: bool) {
+        match std::str::from_utf8(body) {
+            Ok(text) => info!("MQTT publish {} {}", topic, text),
+            Err(_) => info!("MQTT publish {} ({} bytes)", topic, body.len())
+        }
         let client_option = self.mqttclient.lock().await;
         if let Some(client) = client_option.as_ref() {
-            let _ = client.publish(topic, QoS::AtMostOnce, true, body).await;
+            let _ = client.publish(topic, qos, retain, body).await;
         }
     }
 }
@@ -159,11 +560,25 @@ impl StreamReceiverState for AppState {
             cam.image = data.clone();
             let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().subsec_millis();
             cam.last_image = now;
-            for sender in cam.senders.iter() {
-                match sender.send(cam.image.clone()) {
-                    Err(_) => {},
-                    _ => {}
-                }
+            let frame_time: DateTime<Utc> = SystemTime::now().into();
+            let resumed = cam.last_frame.is_none_or(|last| frame_time - last > chrono::Duration::seconds(5));
+            cam.last_frame = Some(frame_time);
+            cam.frame_id = cam.frame_id.wrapping_add(1);
+            if resumed {
+                cam.stream_started = Some(frame_time);
+                cam.stream_frames = 0;
+                cam.stream_bytes = 0;
+            }
+            cam.stream_frames += 1;
+            cam.stream_bytes += data.len() as u64;
+            if resumed {
+                self.notify(UiEvent::StreamStarted { camera: cam.name.to_owned() });
+            }
+            let image = cam.image.clone();
+            cam.senders.retain(|sender| !matches!(sender.try_send(image.clone()), Err(TrySendError::Closed(_))));
+            let motion = motion::effective_config(cam);
+            if motion.enabled && !cam.disabled {
+                self.motion.submit(&cam.name, &motion, &cam.regions, data);
             }
         }
     }
@@ -0,0 +1,480 @@
+use std::{collections::{BTreeMap, HashMap}, env, ffi::CString, fs, mem::MaybeUninit, os::unix::fs::MetadataExt, path::{Path, PathBuf}, time::Duration};
+
+use actix_web::{get, web, HttpResponse};
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use serde::Serialize;
+use tokio::{sync::{Mutex, Notify}, task, time};
+use utoipa::ToSchema;
+
+use crate::{auth::Identity, error::ApiError, recording::segment_files, state::{AppState, CameraFilter}, utils::safe_name};
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Hash, Debug, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Area {
+    Images,
+    Recordings
+}
+
+impl Area {
+    fn name(&self) -> &'static str {
+        match self {
+            Area::Images => "images",
+            Area::Recordings => "recordings"
+        }
+    }
+
+    fn sidecar(&self) -> &'static str {
+        match self {
+            Area::Images => "json",
+            Area::Recordings => "idx"
+        }
+    }
+}
+
+struct AreaSettings {
+    area: Area,
+    folder: String,
+    max_bytes: u64,
+    max_age_hours: i64
+}
+
+impl AreaSettings {
+    fn from_env(area: Area, folder: &str, prefix: &str, default_age_hours: i64) -> Option<Self> {
+        let hours = env::var(format!("{}_MAX_AGE_HOURS", prefix)).ok().and_then(|v| v.parse().ok());
+        let days = env::var(format!("{}_MAX_AGE_DAYS", prefix)).ok().and_then(|v| v.parse::<i64>().ok());
+        Some(AreaSettings {
+            area,
+            folder: env::var(folder).ok()?,
+            max_bytes: env::var(format!("{}_MAX_MB", prefix)).ok().and_then(|v| v.parse::<u64>().ok()).unwrap_or(0) * 1024 * 1024,
+            max_age_hours: hours.or(days.map(|d| d * 24)).unwrap_or(default_age_hours)
+        })
+    }
+}
+
+pub struct StorageSettings {
+    areas: Vec<AreaSettings>,
+    min_free_bytes: u64,
+    interval_secs: u64
+}
+
+impl StorageSettings {
+    fn from_env() -> Self {
+        let areas = [
+            AreaSettings::from_env(Area::Images, "IMAGE_FOLDER", "IMAGE", 0),
+            AreaSettings::from_env(Area::Recordings, "RECORD_FOLDER", "RECORD", 72)
+        ];
+        StorageSettings {
+            areas: areas.into_iter().flatten().collect(),
+            min_free_bytes: env::var("STORAGE_MIN_FREE_MB").ok().and_then(|v| v.parse::<u64>().ok()).unwrap_or(0) * 1024 * 1024,
+            interval_secs: env::var("STORAGE_INTERVAL_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(60)
+        }
+    }
+
+    fn area(&self, area: Area) -> Option<&AreaSettings> {
+        self.areas.iter().find(|a| a.area == area)
+    }
+}
+
+#[derive(Serialize, Clone, Default, ToSchema)]
+pub struct CameraUsage {
+    bytes: u64,
+    files: u64,
+    oldest: Option<DateTime<Utc>>
+}
+
+#[derive(Serialize, Clone, ToSchema)]
+pub struct AreaStatus {
+    area: Area,
+    folder: String,
+    total_bytes: u64,
+    files: u64,
+    free_bytes: Option<u64>,
+    max_bytes: u64,
+    max_age_hours: i64,
+    cameras: BTreeMap<String, CameraUsage>
+}
+
+#[derive(Serialize, Clone, Default, ToSchema)]
+pub struct StorageStatus {
+    areas: Vec<AreaStatus>,
+    min_free_bytes: u64,
+    deleted_files: u64,
+    deleted_bytes: u64,
+    last_run: Option<DateTime<Utc>>
+}
+
+pub struct Storage {
+    settings: StorageSettings,
+    status: Mutex<StorageStatus>,
+    wake: Notify
+}
+
+impl Storage {
+    pub fn from_env() -> Self {
+        Storage { settings: StorageSettings::from_env(), status: Mutex::new(StorageStatus::default()), wake: Notify::new() }
+    }
+
+    pub fn wake(&self) {
+        self.wake.notify_one();
+    }
+
+    pub async fn status(&self) -> StorageStatus {
+        self.status.lock().await.clone()
+    }
+}
+
+struct StoredFile {
+    area: Area,
+    path: PathBuf,
+    name: String,
+    camera: String,
+    modified: DateTime<Utc>,
+    size: u64
+}
+
+#[allow(clippy::unnecessary_cast)]
+fn free_space(folder: &str) -> Option<u64> {
+    let path = CString::new(folder).ok()?;
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+    if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    let stat = unsafe { stat.assume_init() };
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+fn legacy_owner(name: &str, cameras: &[String]) -> String {
+    cameras.iter()
+        .filter(|cam| name.starts_with(&format!("{}-", cam)))
+        .max_by_key(|cam| cam.len())
+        .cloned()
+        .unwrap_or("unknown".to_string())
+}
+
+fn stored_file(area: Area, path: PathBuf, name: String, camera: String) -> Option<StoredFile> {
+    let meta = fs::metadata(&path).ok().filter(|m| m.is_file())?;
+    let sidecar = fs::metadata(path.with_extension(area.sidecar())).map(|m| m.len()).unwrap_or(0);
+    Some(StoredFile { area, modified: meta.modified().ok()?.into(), size: meta.len() + sidecar, path, name, camera })
+}
+
+fn camera_for_folder(folder: String, cameras: &[String]) -> String {
+    cameras.iter().find(|cam| safe_name(cam) == folder).cloned().unwrap_or(folder)
+}
+
+fn scan_images(folder: &str, cameras: &[String]) -> Vec<StoredFile> {
+    let Ok(entries) = fs::read_dir(folder) else { return vec![] };
+    let mut files = vec![];
+    for entry in entries.flatten() {
+        let Ok(name) = entry.file_name().into_string() else { continue };
+        if !entry.path().is_dir() {
+            files.extend(stored_file(Area::Images, entry.path(), name.to_owned(), legacy_owner(&name, cameras)));
+            continue;
+        }
+        let camera = camera_for_folder(name, cameras);
+        for day in fs::read_dir(entry.path()).into_iter().flatten().flatten() {
+            let Ok(day_name) = day.file_name().into_string() else { continue };
+            for image in fs::read_dir(day.path()).into_iter().flatten().flatten() {
+                let Ok(image_name) = image.file_name().into_string() else { continue };
+                if image.path().extension().is_some_and(|e| e == Area::Images.sidecar()) {
+                    continue;
+                }
+                files.extend(stored_file(Area::Images, image.path(), format!("{}/{}", day_name, image_name), camera.to_owned()));
+            }
+        }
+    }
+    files
+}
+
+fn scan_recordings(folder: &str, cameras: &[String]) -> Vec<StoredFile> {
+    let Ok(entries) = fs::read_dir(folder) else { return vec![] };
+    let mut files = vec![];
+    for entry in entries.flatten() {
+        let Ok(name) = entry.file_name().into_string() else { continue };
+        let camera = camera_for_folder(name, cameras);
+        let mut segments = segment_files(&entry.path());
+        segments.pop();
+        for path in segments {
+            let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default().to_string();
+            files.extend(stored_file(Area::Recordings, path, name, camera.to_owned()));
+        }
+    }
+    files
+}
+
+fn device(folder: &str) -> Option<u64> {
+    fs::metadata(folder).ok().map(|m| m.dev())
+}
+
+fn remove(file: &StoredFile, folder: &str) -> bool {
+    if let Err(err) = fs::remove_file(&file.path) {
+        warn!("Could not remove {} {}: {}", file.area.name(), file.path.display(), err);
+        return false;
+    }
+    let _ = fs::remove_file(file.path.with_extension(file.area.sidecar()));
+    if let Some(day) = file.path.parent().filter(|day| *day != Path::new(folder)) {
+        let _ = fs::remove_dir(day);
+    }
+    true
+}
+
+fn enforce(settings: &StorageSettings, cameras: &[String], status: &mut StorageStatus) -> HashMap<String, Vec<String>> {
+    let now = Utc::now();
+    let mut files: Vec<StoredFile> = settings.areas.iter().flat_map(|area| match area.area {
+        Area::Images => scan_images(&area.folder, cameras),
+        Area::Recordings => scan_recordings(&area.folder, cameras)
+    }).collect();
+    files.sort_by_key(|f| f.modified);
+    let mut totals: HashMap<Area, u64> = HashMap::new();
+    for file in files.iter() {
+        *totals.entry(file.area).or_default() += file.size;
+    }
+    let mut deleted: Vec<StoredFile> = vec![];
+    let mut kept: Vec<StoredFile> = vec![];
+    for file in files {
+        let Some(area) = settings.area(file.area) else { continue };
+        let total = totals.get(&file.area).copied().unwrap_or(0);
+        let expired = area.max_age_hours > 0 && (now - file.modified).num_hours() >= area.max_age_hours;
+        let over_quota = area.max_bytes > 0 && total > area.max_bytes;
+        if (expired || over_quota) && remove(&file, &area.folder) {
+            totals.insert(file.area, total - file.size);
+            deleted.push(file);
+        } else {
+            kept.push(file);
+        }
+    }
+    if settings.min_free_bytes > 0 {
+        let mut devices: Vec<(u64, &AreaSettings)> = settings.areas.iter().filter_map(|area| Some((device(&area.folder)?, area))).collect();
+        devices.dedup_by_key(|(dev, _)| *dev);
+        for (dev, area) in devices {
+            let Some(free) = free_space(&area.folder) else { continue };
+            let shortfall = settings.min_free_bytes.saturating_sub(free);
+            if shortfall == 0 {
+                continue;
+            }
+            let on_device = |file: &StoredFile| settings.area(file.area).and_then(|a| device(&a.folder)) == Some(dev);
+            let deletable: u64 = kept.iter().filter(|f| on_device(f)).map(|f| f.size).sum();
+            if deletable < shortfall {
+                warn!("Free space on {} is {} bytes short, but only {} bytes of images and recordings can be removed", area.folder, shortfall, deletable);
+                continue;
+            }
+            let mut freed = 0;
+            let mut remaining = vec![];
+            for file in kept {
+                let folder = settings.area(file.area).map(|a| a.folder.as_str()).unwrap_or_default();
+                if freed < shortfall && on_device(&file) && remove(&file, folder) {
+                    freed += file.size;
+                    *totals.entry(file.area).or_default() -= file.size;
+                    deleted.push(file);
+                } else {
+                    remaining.push(file);
+                }
+            }
+            kept = remaining;
+        }
+    }
+    if !deleted.is_empty() {
+        info!("Removed {} old images and recordings", deleted.len());
+    }
+    status.deleted_files += deleted.len() as u64;
+    status.deleted_bytes += deleted.iter().map(|f| f.size).sum::<u64>();
+    status.areas = settings.areas.iter().map(|area| {
+        let mut cameras: BTreeMap<String, CameraUsage> = BTreeMap::new();
+        for file in kept.iter().filter(|f| f.area == area.area) {
+            let usage = cameras.entry(file.camera.to_owned()).or_default();
+            usage.bytes += file.size;
+            usage.files += 1;
+            usage.oldest = usage.oldest.or(Some(file.modified));
+        }
+        AreaStatus {
+            area: area.area,
+            folder: area.folder.to_owned(),
+            total_bytes: totals.get(&area.area).copied().unwrap_or(0),
+            files: cameras.values().map(|c| c.files).sum(),
+            free_bytes: free_space(&area.folder),
+            max_bytes: area.max_bytes,
+            max_age_hours: area.max_age_hours,
+            cameras
+        }
+    }).collect();
+    status.min_free_bytes = settings.min_free_bytes;
+    status.last_run = Some(now);
+    let mut images: HashMap<String, Vec<String>> = HashMap::new();
+    for file in deleted.into_iter().filter(|f| f.area == Area::Images) {
+        images.entry(file.camera).or_default().push(file.name);
+    }
+    images
+}
+
+pub fn spawn_storage_manager(state: AppState) {
+    if state.storage.settings.areas.is_empty() {
+        return;
+    }
+    task::spawn(async move {
+        for area in state.storage.settings.areas.iter() {
+            info!("Storage manager watching {} in {}", area.area.name(), area.folder);
+        }
+        let mut interval = time::interval(Duration::from_secs(state.storage.settings.interval_secs.max(1)));
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {},
+                _ = state.storage.wake.notified() => {}
+            }
+            let cameras = state.for_all_cameras(&CameraFilter::all(), |cam| cam.name.to_owned()).await;
+            let mut status = state.storage.status().await;
+            let storage = state.storage.clone();
+            let Ok((status, deleted)) = task::spawn_blocking(move || {
+                let deleted = enforce(&storage.settings, &cameras, &mut status);
+                (status, deleted)
+            }).await else { continue };
+            *state.storage.status.lock().await = status;
+            for (camera, names) in deleted {
+                state.for_mut_camera(&camera, |cam_info| {
+                    for mvt in cam_info.moves.iter_mut() {
+                        mvt.images.retain(|image| !names.contains(image));
+                    }
+                }).await;
+            }
+        }
+    });
+}
+
+#[utoipa::path(get, path = "/api/admin/storage", responses((status = 200, body = StorageStatus)))]
+#[get("/api/admin/storage")]
+async fn get_storage(state: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(state.storage.status().await))
+}
+
+fn label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[get("/metrics")]
+async fn get_metrics(state: web::Data<AppState>, identity: Identity) -> Result<HttpResponse, ApiError> {
+    let status = state.storage.status().await;
+    let mut body = String::new();
+    body.push_str("# TYPE camserver_storage_bytes gauge\n");
+    for area in status.areas.iter() {
+        for (camera, usage) in area.cameras.iter().filter(|(camera, _)| identity.can_view(camera)) {
+            body.push_str(&format!("camserver_storage_bytes{{area=\"{}\",camera=\"{}\"}} {}\n", area.area.name(), label(camera), usage.bytes));
+        }
+    }
+    body.push_str("# TYPE camserver_storage_files gauge\n");
+    for area in status.areas.iter() {
+        for (camera, usage) in area.cameras.iter().filter(|(camera, _)| identity.can_view(camera)) {
+            body.push_str(&format!("camserver_storage_files{{area=\"{}\",camera=\"{}\"}} {}\n", area.area.name(), label(camera), usage.files));
+        }
+    }
+    body.push_str("# TYPE camserver_storage_total_bytes gauge\n");
+    for area in status.areas.iter() {
+        body.push_str(&format!("camserver_storage_total_bytes{{area=\"{}\"}} {}\n", area.area.name(), area.total_bytes));
+    }
+    body.push_str("# TYPE camserver_storage_max_bytes gauge\n");
+    for area in status.areas.iter() {
+        body.push_str(&format!("camserver_storage_max_bytes{{area=\"{}\"}} {}\n", area.area.name(), area.max_bytes));
+    }
+    body.push_str("# TYPE camserver_storage_free_bytes gauge\n");
+    for area in status.areas.iter() {
+        if let Some(free) = area.free_bytes {
+            body.push_str(&format!("camserver_storage_free_bytes{{area=\"{}\"}} {}\n", area.area.name(), free));
+        }
+    }
+    body.push_str(&format!("# TYPE camserver_storage_min_free_bytes gauge\ncamserver_storage_min_free_bytes {}\n", status.min_free_bytes));
+    body.push_str(&format!("# TYPE camserver_storage_deleted_files_total counter\ncamserver_storage_deleted_files_total {}\n", status.deleted_files));
+    body.push_str(&format!("# TYPE camserver_storage_deleted_bytes_total counter\ncamserver_storage_deleted_bytes_total {}\n", status.deleted_bytes));
+    Ok(HttpResponse::Ok().content_type("text/plain; version=0.0.4").body(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, time::SystemTime};
+
+    use super::*;
+
+    fn temp_folder() -> PathBuf {
+        let folder = env::temp_dir().join(format!("camserver-storage-{}", uuid::Uuid::new_v4().simple()));
+        fs::create_dir_all(&folder).unwrap();
+        folder
+    }
+
+    fn write_file(path: &Path, size: usize, age_hours: u64) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, vec![0u8; size]).unwrap();
+        let modified = SystemTime::now() - Duration::from_secs(age_hours * 3600);
+        File::options().write(true).open(path).unwrap().set_modified(modified).unwrap();
+    }
+
+    fn settings(areas: Vec<AreaSettings>, min_free_bytes: u64) -> StorageSettings {
+        StorageSettings { areas, min_free_bytes, interval_secs: 60 }
+    }
+
+    fn area(area: Area, folder: &Path, max_bytes: u64, max_age_hours: i64) -> AreaSettings {
+        AreaSettings { area, folder: folder.to_str().unwrap().to_string(), max_bytes, max_age_hours }
+    }
+
+    #[test]
+    fn quota_removes_oldest_images_first() {
+        let folder = temp_folder();
+        write_file(&folder.join("a/2024-05-01/old.jpg"), 100, 3);
+        write_file(&folder.join("a/2024-05-01/mid.jpg"), 100, 2);
+        write_file(&folder.join("a/2024-05-02/new.jpg"), 100, 1);
+        let settings = settings(vec![area(Area::Images, &folder, 250, 0)], 0);
+        let mut status = StorageStatus::default();
+        let deleted = enforce(&settings, &["a".to_string()], &mut status);
+        assert_eq!(deleted.get("a"), Some(&vec!["2024-05-01/old.jpg".to_string()]));
+        assert!(folder.join("a/2024-05-01/mid.jpg").exists());
+        assert!(folder.join("a/2024-05-02/new.jpg").exists());
+        assert_eq!(status.deleted_files, 1);
+        assert_eq!(status.areas[0].total_bytes, 200);
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn age_limit_removes_expired_files_and_sidecars() {
+        let folder = temp_folder();
+        write_file(&folder.join("a/2024-05-01/old.jpg"), 10, 30);
+        write_file(&folder.join("a/2024-05-01/old.json"), 10, 30);
+        write_file(&folder.join("a/2024-05-02/new.jpg"), 10, 1);
+        let settings = settings(vec![area(Area::Images, &folder, 0, 24)], 0);
+        let mut status = StorageStatus::default();
+        enforce(&settings, &["a".to_string()], &mut status);
+        assert!(!folder.join("a/2024-05-01/old.jpg").exists());
+        assert!(!folder.join("a/2024-05-01/old.json").exists());
+        assert!(!folder.join("a/2024-05-01").exists());
+        assert!(folder.join("a/2024-05-02/new.jpg").exists());
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn newest_recording_segment_is_kept() {
+        let folder = temp_folder();
+        write_file(&folder.join("a/2024-05-01/10-00-00.mjpeg"), 100, 200);
+        write_file(&folder.join("a/2024-05-01/10-10-00.mjpeg"), 100, 100);
+        let settings = settings(vec![area(Area::Recordings, &folder, 1, 72)], 0);
+        let mut status = StorageStatus::default();
+        enforce(&settings, &["a".to_string()], &mut status);
+        assert!(!folder.join("a/2024-05-01/10-00-00.mjpeg").exists());
+        assert!(folder.join("a/2024-05-01/10-10-00.mjpeg").exists());
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn unreachable_free_space_deletes_nothing() {
+        let folder = temp_folder();
+        write_file(&folder.join("a/2024-05-01/old.jpg"), 10, 3);
+        let settings = settings(vec![area(Area::Images, &folder, 0, 0)], u64::MAX);
+        let mut status = StorageStatus::default();
+        let deleted = enforce(&settings, &["a".to_string()], &mut status);
+        assert!(deleted.is_empty());
+        assert!(folder.join("a/2024-05-01/old.jpg").exists());
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn legacy_images_belong_to_longest_matching_camera() {
+        let cameras = ["front".to_string(), "front-door".to_string()];
+        assert_eq!(legacy_owner("front-door-2024.jpg", &cameras), "front-door");
+        assert_eq!(legacy_owner("front-2024.jpg", &cameras), "front");
+        assert_eq!(legacy_owner("back-2024.jpg", &cameras), "unknown");
+    }
+}
@@ -0,0 +1,145 @@
+use std::{env, fs::{self, File}, io::{self, BufReader}, sync::{Arc, RwLock}, time::{Duration, SystemTime}};
+
+use actix_web::{http::{header, uri::Authority}, web, HttpRequest, HttpResponse};
+use log::{error, info};
+use rustls::{crypto::ring::{default_provider, sign::any_supported_type}, server::{ClientHello, ResolvesServerCert}, sign::CertifiedKey, ServerConfig};
+use tokio::{signal::unix::{signal, SignalKind}, task, time};
+
+pub struct TlsSettings {
+    pub cert_path: String,
+    pub key_path: String,
+    pub port: u16,
+    pub redirect_port: Option<u16>
+}
+
+impl TlsSettings {
+    pub fn from_env() -> Option<Self> {
+        let cert_path = env::var("TLS_CERT").ok()?;
+        let key_path = env::var("TLS_KEY").ok()?;
+        let port = env::var("HTTPS_PORT").ok().and_then(|p| p.parse().ok()).unwrap_or(8443);
+        let redirect_port = match env::var("HTTPS_REDIRECT").as_deref() {
+            Ok("true") | Ok("1") => Some(env::var("HTTP_PORT").ok().and_then(|p| p.parse().ok()).unwrap_or(8080)),
+            _ => None
+        };
+        Some(TlsSettings { cert_path, key_path, port, redirect_port })
+    }
+}
+
+#[derive(Debug)]
+struct ReloadingResolver {
+    cert_path: String,
+    key_path: String,
+    current: RwLock<Arc<CertifiedKey>>
+}
+
+impl ReloadingResolver {
+    fn load(cert_path: &str, key_path: &str) -> io::Result<CertifiedKey> {
+        let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert_path)?)).collect::<Result<Vec<_>, _>>()?;
+        let key = rustls_pemfile::private_key(&mut BufReader::new(File::open(key_path)?))?
+            .ok_or(io::Error::new(io::ErrorKind::InvalidData, "no private key found"))?;
+        let signing_key = any_supported_type(&key).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(CertifiedKey::new(certs, signing_key))
+    }
+
+    fn reload(&self) {
+        match ReloadingResolver::load(&self.cert_path, &self.key_path) {
+            Ok(key) => {
+                *self.current.write().unwrap() = Arc::new(key);
+                info!("TLS certificate reloaded from {}", self.cert_path);
+            },
+            Err(err) => error!("TLS certificate reload failed, keeping current: {}", err)
+        }
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        let cert = fs::metadata(&self.cert_path).and_then(|m| m.modified()).ok()?;
+        let key = fs::metadata(&self.key_path).and_then(|m| m.modified()).ok()?;
+        Some(cert.max(key))
+    }
+}
+
+impl ResolvesServerCert for ReloadingResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+pub fn server_config(settings: &TlsSettings) -> io::Result<ServerConfig> {
+    let key = ReloadingResolver::load(&settings.cert_path, &settings.key_path)?;
+    let resolver = Arc::new(ReloadingResolver {
+        cert_path: settings.cert_path.to_owned(),
+        key_path: settings.key_path.to_owned(),
+        current: RwLock::new(Arc::new(key))
+    });
+    spawn_reloader(resolver.clone());
+    let config = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?
+        .with_no_client_auth()
+        .with_cert_resolver(resolver);
+    Ok(config)
+}
+
+fn spawn_reloader(resolver: Arc<ReloadingResolver>) {
+    task::spawn(async move {
+        let mut hangup = signal(SignalKind::hangup()).unwrap();
+        let mut interval = time::interval(Duration::from_secs(30));
+        let mut last_modified = resolver.modified();
+        loop {
+            tokio::select! {
+                _ = hangup.recv() => {
+                    info!("SIGHUP received, reloading TLS certificate");
+                    resolver.reload();
+                    last_modified = resolver.modified();
+                },
+                _ = interval.tick() => {
+                    let modified = resolver.modified();
+                    if modified.is_some() && modified != last_modified {
+                        resolver.reload();
+                        last_modified = modified;
+                    }
+                }
+            }
+        }
+    });
+}
+
+fn https_location(authority: &str, port: u16, path: &str) -> String {
+    let authority = authority.parse::<Authority>().ok();
+    let host = authority.as_ref().map(|a| a.host()).filter(|h| !h.is_empty()).unwrap_or("localhost");
+    let host = if host.contains(':') && !host.starts_with('[') { format!("[{}]", host) } else { host.to_string() };
+    match port {
+        443 => format!("https://{}{}", host, path),
+        port => format!("https://{}:{}{}", host, port, path)
+    }
+}
+
+pub async fn redirect_to_https(req: HttpRequest, port: web::Data<u16>) -> HttpResponse {
+    let path = req.uri().path_and_query().map(|p| p.as_str()).unwrap_or("/");
+    let location = https_location(req.connection_info().host(), *port.get_ref(), path);
+    HttpResponse::PermanentRedirect().insert_header((header::LOCATION, location)).finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn location_keeps_host_and_replaces_port() {
+        assert_eq!(https_location("cams.local:8080", 8443, "/cam/a?x=1"), "https://cams.local:8443/cam/a?x=1");
+        assert_eq!(https_location("cams.local", 443, "/"), "https://cams.local/");
+        assert_eq!(https_location("192.168.1.10:8080", 443, "/"), "https://192.168.1.10/");
+    }
+
+    #[test]
+    fn location_handles_ipv6_literals() {
+        assert_eq!(https_location("[::1]:8080", 8443, "/"), "https://[::1]:8443/");
+        assert_eq!(https_location("[fe80::1]", 443, "/x"), "https://[fe80::1]/x");
+    }
+
+    #[test]
+    fn location_falls_back_to_localhost() {
+        assert_eq!(https_location("", 8443, "/"), "https://localhost:8443/");
+        assert_eq!(https_location("bad host", 443, "/"), "https://localhost/");
+    }
+}
@@ -0,0 +1,124 @@
+use std::{env, process};
+
+use log::error;
+use regex::Regex;
+use rumqttc::QoS;
+
+#[derive(Clone, Copy)]
+pub struct TopicPolicy {
+    pub qos: QoS,
+    pub retain: bool
+}
+
+impl TopicPolicy {
+    pub fn from_env(name: &str, qos: QoS, retain: bool) -> Self {
+        let qos = match env::var(format!("{}_QOS", name)).as_deref() {
+            Ok("0") => QoS::AtMostOnce,
+            Ok("1") => QoS::AtLeastOnce,
+            Ok("2") => QoS::ExactlyOnce,
+            _ => qos
+        };
+        let retain = match env::var(format!("{}_RETAIN", name)).as_deref() {
+            Ok("true") | Ok("1") => true,
+            Ok("false") | Ok("0") => false,
+            _ => retain
+        };
+        TopicPolicy { qos, retain }
+    }
+}
+
+#[derive(Clone)]
+pub struct TopicTemplate {
+    template: String,
+    regex: Regex
+}
+
+impl TopicTemplate {
+    pub fn new(template: &str) -> Self {
+        let retext = format!("^{}$", regex::escape(template).replace("\\{cam\\}", "([^/]+)"));
+        let regex = Regex::new(&retext).unwrap();
+        TopicTemplate { template: template.to_string(), regex }
+    }
+
+    fn from_env(name: &str, default: &str) -> Self {
+        let template = env::var(name).unwrap_or(default.to_string());
+        if !template.contains("{cam}") {
+            error!("{} must contain {{cam}}, got {:?}", name, template);
+            process::exit(1);
+        }
+        TopicTemplate::new(&template)
+    }
+
+    pub fn topic(&self, cam: &str) -> String {
+        self.template.replace("{cam}", cam)
+    }
+
+    pub fn subscription(&self) -> String {
+        self.template.replace("{cam}", "+")
+    }
+
+    pub fn camera<'a>(&self, topic: &'a str) -> Option<&'a str> {
+        self.regex.captures(topic).and_then(|c| c.get(1)).map(|m| m.as_str())
+    }
+}
+
+#[derive(Clone)]
+pub struct Topics {
+    pub stat: TopicTemplate,
+    pub moves: TopicTemplate,
+    pub cmd: TopicTemplate,
+    pub motion: TopicTemplate,
+    pub online: TopicTemplate,
+    pub snapshot: TopicTemplate,
+    pub arm: String,
+    pub arm_set: String,
+    pub arm_zone: TopicTemplate,
+    pub arm_zone_set: TopicTemplate
+}
+
+impl Topics {
+    pub fn from_env() -> Self {
+        let arm = env::var("MQTT_TOPIC_ARM").unwrap_or("camserver/arm".to_string());
+        Topics {
+            stat: TopicTemplate::from_env("MQTT_TOPIC_STAT", "home/cams/{cam}/stat"),
+            moves: TopicTemplate::from_env("MQTT_TOPIC_MOVE", "home/cams/{cam}/move"),
+            cmd: TopicTemplate::from_env("MQTT_TOPIC_CMD", "home/cams/{cam}/cmd"),
+            motion: TopicTemplate::from_env("MQTT_TOPIC_MOTION", "camserver/{cam}/motion"),
+            online: TopicTemplate::from_env("MQTT_TOPIC_ONLINE", "camserver/{cam}/online"),
+            snapshot: TopicTemplate::from_env("MQTT_TOPIC_SNAPSHOT", "camserver/{cam}/snapshot"),
+            arm_set: format!("{}/set", arm),
+            arm_zone: TopicTemplate::new(&format!("{}/zone/{{cam}}", arm)),
+            arm_zone_set: TopicTemplate::new(&format!("{}/zone/{{cam}}/set", arm)),
+            arm
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn camera_is_extracted_from_topic() {
+        let template = TopicTemplate::new("home/cams/{cam}/stat");
+        assert_eq!(template.camera("home/cams/front-door/stat"), Some("front-door"));
+        assert_eq!(template.camera("home/cams/cam.2/stat"), Some("cam.2"));
+        assert_eq!(template.camera("home/cams/a/b/stat"), None);
+        assert_eq!(template.camera("home/cams/a/move"), None);
+        assert_eq!(template.camera("x/home/cams/a/stat"), None);
+    }
+
+    #[test]
+    fn literal_parts_are_escaped() {
+        let template = TopicTemplate::new("cams.v1/{cam}/stat");
+        assert_eq!(template.camera("cams.v1/a/stat"), Some("a"));
+        assert_eq!(template.camera("camsxv1/a/stat"), None);
+    }
+
+    #[test]
+    fn topic_and_subscription_fill_the_placeholder() {
+        let template = TopicTemplate::new("camserver/{cam}/motion");
+        assert_eq!(template.topic("a"), "camserver/a/motion");
+        assert_eq!(template.subscription(), "camserver/+/motion");
+    }
+}
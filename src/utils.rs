@@ -1,4 +1,4 @@
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::{net::{IpAddr, UdpSocket}, time::{SystemTime, UNIX_EPOCH}};
 
 fn ts() -> u32 {
     SystemTime::now().duration_since(UNIX_EPOCH).unwrap().subsec_millis()
@@ -23,4 +23,52 @@ impl Timer {
         let dur = if now > self.start { now - self.start } else {0};
         dur
     }
-}
\ No newline at end of file
+}
+
+pub fn local_ip_towards(remote: &str) -> Option<IpAddr> {
+    let remote_ip: IpAddr = remote.parse().ok()?;
+    let bind_addr = if remote_ip.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+    let socket = UdpSocket::bind(bind_addr).ok()?;
+    socket.connect((remote_ip, 10999)).ok()?;
+    socket.local_addr().ok().map(|addr| addr.ip())
+}
+
+pub fn safe_name(name: &str) -> String {
+    let safe: String = name.trim().chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' { c } else { '_' }).collect();
+    let safe = safe.trim_start_matches('.');
+    if safe.is_empty() { "_".to_string() } else { safe.to_string() }
+}
+
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty() && safe_name(name) == name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn safe_name_replaces_unsafe_characters() {
+        assert_eq!(safe_name("front-door_1.cam"), "front-door_1.cam");
+        assert_eq!(safe_name("a/b\\c d"), "a_b_c_d");
+        assert_eq!(safe_name("../etc"), "_etc");
+        assert_eq!(safe_name("..hidden"), "hidden");
+        assert_eq!(safe_name("  spaced  "), "spaced");
+        assert_eq!(safe_name(""), "_");
+        assert_eq!(safe_name(".."), "_");
+        assert_eq!(safe_name("caméra"), "cam_ra");
+    }
+
+    #[test]
+    fn valid_name_rejects_anything_safe_name_would_change() {
+        assert!(valid_name("front-door"));
+        assert!(valid_name("cam.2"));
+        assert!(valid_name("a_b"));
+        assert!(!valid_name(""));
+        assert!(!valid_name(".."));
+        assert!(!valid_name(".hidden"));
+        assert!(!valid_name("a/b"));
+        assert!(!valid_name("x');alert(1)//"));
+        assert!(!valid_name(" padded"));
+    }
+}
@@ -0,0 +1,100 @@
+use std::{sync::Arc, time::Duration};
+
+use actix_web::{get, rt, web, HttpRequest, HttpResponse};
+use actix_ws::{CloseCode, CloseReason, Message, Session};
+use chrono::{DateTime, Utc};
+use log::info;
+use serde::Deserialize;
+use tokio::time;
+
+use crate::{auth::Identity, error::ApiError, sessions::ViewerGuard, state::AppState};
+
+const DEFAULT_FPS: u32 = 10;
+const MAX_FPS: u32 = 30;
+
+#[derive(Deserialize)]
+#[serde(tag = "cmd", rename_all = "lowercase")]
+enum ControlMessage {
+    Pause,
+    Resume,
+    Fps { value: u32 }
+}
+
+struct Frame {
+    id: u32,
+    captured: DateTime<Utc>,
+    data: Arc<Vec<u8>>
+}
+
+impl Frame {
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(16 + self.data.len());
+        buf.extend_from_slice(&self.id.to_be_bytes());
+        buf.extend_from_slice(&(self.captured.timestamp_millis() as u64).to_be_bytes());
+        buf.extend_from_slice(&(self.data.len() as u32).to_be_bytes());
+        buf.extend_from_slice(&self.data);
+        buf
+    }
+}
+
+fn frame_interval(fps: u32) -> time::Interval {
+    let mut interval = time::interval(Duration::from_millis(1000 / fps.clamp(1, MAX_FPS) as u64));
+    interval.set_missed_tick_behavior(time::MissedTickBehavior::Skip);
+    interval
+}
+
+async fn run_stream(state: AppState, cam: String, guard: ViewerGuard, mut session: Session, mut msg_stream: actix_ws::MessageStream) {
+    let mut interval = frame_interval(DEFAULT_FPS);
+    let mut paused = false;
+    let mut last_sent: Option<u32> = None;
+    let mut close_reason = None;
+    loop {
+        tokio::select! {
+            msg = msg_stream.recv() => {
+                match msg {
+                    Some(Ok(Message::Text(text))) => {
+                        match serde_json::from_str::<ControlMessage>(&text) {
+                            Ok(ControlMessage::Pause) => paused = true,
+                            Ok(ControlMessage::Resume) => paused = false,
+                            Ok(ControlMessage::Fps { value }) => interval = frame_interval(value),
+                            Err(_) => {}
+                        }
+                    },
+                    Some(Ok(Message::Ping(bytes))) => {
+                        let _ = session.pong(&bytes).await;
+                    },
+                    Some(Ok(Message::Close(_))) | None | Some(Err(_)) => break,
+                    _ => {}
+                }
+            },
+            _ = interval.tick() => {
+                if paused { continue; }
+                let Some(frame) = state.for_camera(&cam, |cam_info| {
+                    cam_info.last_frame.map(|captured| Frame { id: cam_info.frame_id, captured, data: cam_info.image.clone() })
+                }).await else {
+                    close_reason = Some(CloseReason { code: CloseCode::Away, description: Some("camera removed".to_string()) });
+                    break;
+                };
+                if let Some(frame) = frame {
+                    if last_sent != Some(frame.id) && !frame.data.is_empty() {
+                        last_sent = Some(frame.id);
+                        if session.binary(frame.encode()).await.is_err() { break; }
+                    }
+                }
+            }
+        }
+    }
+    info!("WS stream for {} closed", cam);
+    let _ = session.close(close_reason).await;
+    drop(guard);
+}
+
+#[get("/ws/{cam}/stream")]
+async fn ws_stream(state: web::Data<AppState>, identity: Identity, cam_name: web::Path<String>, req: HttpRequest, body: web::Payload) -> Result<HttpResponse, ApiError> {
+    identity.authorize(&cam_name)?;
+    let (response, session, msg_stream) = actix_ws::handle(&req, body).map_err(|err| ApiError::BadRequest(err.to_string()))?;
+    let guard = ViewerGuard::join(&state, &cam_name).await?;
+    info!("WS stream for {} opened by {}", cam_name, identity.name);
+    rt::spawn(run_stream(state.get_ref().clone(), cam_name.into_inner(), guard, session, msg_stream));
+    Ok(response)
+}
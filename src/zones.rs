@@ -0,0 +1,60 @@
+use actix_web::{get, post, web, HttpResponse};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::{auth::Identity, commands::StateRequest, daynight, error::ApiError, state::{AppState, CameraFilter}};
+
+#[derive(Serialize, ToSchema)]
+pub struct ZoneView {
+    zone: String,
+    cameras: Vec<String>
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ZoneCommandResult {
+    camera: String,
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>
+}
+
+#[utoipa::path(get, path = "/api/zones", responses((status = 200, body = [ZoneView])))]
+#[get("/api/zones")]
+async fn get_zones(state: web::Data<AppState>, identity: Identity) -> Result<HttpResponse, ApiError> {
+    let mut zones = vec![];
+    for zone in state.zones().await {
+        let mut cameras = state.for_all_cameras(&CameraFilter::zone(&zone), |cam| cam.name.to_owned()).await;
+        cameras.retain(|cam| identity.can_view(cam));
+        if !cameras.is_empty() {
+            zones.push(ZoneView { zone, cameras });
+        }
+    }
+    Ok(HttpResponse::Ok().json(zones))
+}
+
+#[utoipa::path(post, path = "/api/zones/{zone}/state", params(("zone" = String, Path)), request_body = StateRequest, responses((status = 200, body = [ZoneCommandResult]), (status = 404), (status = 422), (status = 503)))]
+#[post("/api/zones/{zone}/state")]
+async fn post_zone_state(state: web::Data<AppState>, identity: Identity, zone: web::Path<String>, body: web::Json<StateRequest>) -> Result<HttpResponse, ApiError> {
+    body.validate().map_err(ApiError::Invalid)?;
+    if body.actions().is_some() {
+        return Err(ApiError::Invalid("stream actions cannot be sent to a zone".to_string()));
+    }
+    let filter = CameraFilter { enabled_only: true, ..CameraFilter::zone(&zone) };
+    let mut cameras = state.for_all_cameras(&filter, |cam| cam.name.to_owned()).await;
+    cameras.retain(|cam| identity.can_view(cam));
+    if cameras.is_empty() {
+        return Err(ApiError::NotFound(format!("zone {}", zone)));
+    }
+    if !state.mqtt_connected() {
+        return Err(ApiError::MqttUnavailable);
+    }
+    let mut results = vec![];
+    for camera in cameras {
+        let result = state.send_command(&camera, &body).await;
+        if result.is_ok() {
+            daynight::manual_override(&state, &camera, &body).await;
+        }
+        results.push(ZoneCommandResult { camera, ok: result.is_ok(), error: result.err().map(|e| e.to_string()) });
+    }
+    Ok(HttpResponse::Ok().json(results))
+}
@@ -1,5 +1,4 @@
 var cams = {};
-var udpip = "";
 
 function addCam(cam, ip) {
     cams[cam] = {
@@ -8,35 +7,83 @@ function addCam(cam, ip) {
     };
 }
 
-function setUdpIP(ip) {
-    udpip = ip;
-}
-
 function streamToggle(cam, type, stream_id) {
     var butt = document.getElementById(cam + type + "streambut");
     var img = document.getElementById(cam + "img");
     if(cams[cam].streaming) {
         cams[cam].streaming = false;
+        document.getElementById(cam + "container").classList.remove("streaming");
         if(type == 'http') {
             butt.innerHTML = "HTTP Stream";
             img.src = "";
-        } else if(type == 'udp') {
-            butt.innerHTML = "UDP Stream";
-            img.src = "";
-            postState(cam, {streamto:"", streamid:0});
+        } else if(type == 'ws') {
+            butt.innerHTML = "WS Stream";
+            stopWsStream(cam);
         }
     } else {
         butt.innerHTML = "Stop";
         cams[cam].streaming = true;
         if(type == 'http') {
             img.src = "http://" + cams[cam].ip + "/stream";
-        } else if(type == 'udp') {
-            img.src = "/" + cam + "/stream";
-            postState(cam, {streamto: udpip, streamid: stream_id});
+        } else if(type == 'ws') {
+            startWsStream(cam);
+        }
+    }
+}
+
+function startWsStream(cam) {
+    var canvas = document.getElementById(cam + "canvas");
+    var latency = document.getElementById(cam + "latency");
+    var proto = window.location.protocol == "https:" ? "wss://" : "ws://";
+    var socket = new WebSocket(proto + window.location.host + "/ws/" + cam + "/stream");
+    socket.binaryType = "arraybuffer";
+    var frames = 0;
+    var since = Date.now();
+    socket.onmessage = async (msg) => {
+        var view = new DataView(msg.data);
+        var captured = Number(view.getBigUint64(4));
+        var size = view.getUint32(12);
+        var bitmap = await createImageBitmap(new Blob([new Uint8Array(msg.data, 16, size)], {type: "image/jpeg"}));
+        canvas.width = bitmap.width;
+        canvas.height = bitmap.height;
+        canvas.getContext("2d").drawImage(bitmap, 0, 0);
+        frames++;
+        var now = Date.now();
+        if(now - since >= 1000) {
+            latency.innerHTML = (now - captured) + " ms, " + Math.round(frames * 1000 / (now - since)) + " fps";
+            frames = 0;
+            since = now;
         }
+    };
+    cams[cam].socket = socket;
+    canvas.style.display = "flex";
+}
+
+function stopWsStream(cam) {
+    if(cams[cam].socket) {
+        cams[cam].socket.close();
+        cams[cam].socket = null;
     }
+    document.getElementById(cam + "canvas").style.display = "none";
+    document.getElementById(cam + "latency").innerHTML = "";
 }
 
+function wsControl(cam, body) {
+    if(cams[cam].socket && cams[cam].socket.readyState == WebSocket.OPEN) {
+        cams[cam].socket.send(JSON.stringify(body));
+    }
+}
+
+function wsFps(cam, val) {
+    wsControl(cam, {cmd: "fps", value: parseInt(val)});
+}
+
+document.addEventListener("visibilitychange", () => {
+    for(var cam in cams) {
+        wsControl(cam, {cmd: document.hidden ? "pause" : "resume"});
+    }
+});
+
 function filter(cam, val) {
     postState(cam, {filter: val ? 1 : 0});
 }
@@ -59,6 +106,41 @@ async function postState(cam, body) {
 
 async function post(url, body) {
     var resp = await window.fetch(url, {method:"POST", headers:{"Content-Type":"application/json"}, body:JSON.stringify(body)}); 
+    await showError(resp);
+}
+
+async function showError(resp) {
+    if(!resp.ok) {
+        var json = await resp.json().catch(() => ({message: resp.statusText}));
+        window.alert(json.message);
+    }
+}
+
+async function adminUpdate(cam, body) {
+    var resp = await window.fetch("/api/admin/cameras/" + cam, {method:"PATCH", headers:{"Content-Type":"application/json"}, body:JSON.stringify(body)});
+    await showError(resp);
+}
+
+function splitZones(value) {
+    return value.split(",").map(z => z.trim()).filter(z => z.length > 0);
+}
+
+async function zoneState(zone, body) {
+    var resp = await window.fetch("/api/zones/" + zone + "/state", {method:"POST", headers:{"Content-Type":"application/json"}, body:JSON.stringify(body)});
+    await showError(resp);
+}
+
+async function armSet(zone, mode) {
+    var url = zone == null ? "/api/arming" : "/api/arming/zones/" + zone;
+    var resp = await window.fetch(url, {method:"PUT", headers:{"Content-Type":"application/json"}, body:JSON.stringify({mode: mode == "" ? null : mode})});
+    await showError(resp);
+}
+
+async function adminDelete(cam) {
+    if(!window.confirm("Delete camera " + cam + "? It will be added again if it is still running and reports in.")) return;
+    var resp = await window.fetch("/api/admin/cameras/" + cam, {method:"DELETE"});
+    await showError(resp);
+    if(resp.ok) window.location = "/";
 }
 
 async function showMovement(cam) {
@@ -66,11 +148,113 @@ async function showMovement(cam) {
     var json = await resp.json();
     var list = json.movements;
     list.sort((a, b) => b.timestamp.localeCompare(a.timestamp));
-    var content = "<div class='moveframe'>" + list.map(m => "- " + new Date(m.timestamp)).join('<br>') + "</div>";
+    var content = "<div class='moveframe'>" + list.map(m => "- " + new Date(m.timestamp) + " (" + m.count + ")").join('<br>') + "</div>";
     var div = document.createElement("div");
     div.className = "moveoverlay";
     div.id = "moveoverlay";
     div.innerHTML = content;
     div.onclick = (event) => {document.body.removeChild(document.getElementById("moveoverlay"));};
     document.body.appendChild(div);
-}
\ No newline at end of file
+}
+
+function camElements(cam) {
+    var refs = Array.from(document.querySelectorAll(".camref")).filter((el) => el.dataset.cam == cam);
+    return [document.getElementById(cam + "container")].concat(refs);
+}
+
+function listenEvents() {
+    var source = new EventSource("/api/events");
+    source.onmessage = (msg) => {
+        var event = JSON.parse(msg.data);
+        var container = document.getElementById(event.camera + "container");
+        if(event.type == 'camera_added') {
+            if(container == null) window.location.reload();
+        } else if(container == null) {
+            return;
+        } else if(event.type == 'stat') {
+            cams[event.camera].ip = event.ip;
+            document.getElementById(event.camera + "ip").innerHTML = event.ip;
+            document.getElementById(event.camera + "lum").innerHTML = "lum " + event.lum;
+        } else if(event.type == 'online') {
+            camElements(event.camera).forEach((el) => el.classList.toggle("offline", !event.online));
+        } else if(event.type == 'motion') {
+            camElements(event.camera).forEach((el) => el.classList.add("moving"));
+        } else if(event.type == 'motion_ended') {
+            camElements(event.camera).forEach((el) => el.classList.remove("moving"));
+        } else if(event.type == 'stream_started') {
+            container.classList.add("streaming");
+        }
+    };
+}
+
+var regionEditors = {};
+
+function regionEditor(cam, regions) {
+    var editor = {regions: regions, points: []};
+    regionEditors[cam] = editor;
+    var canvas = document.getElementById(cam + "regioncanvas");
+    canvas.onclick = function(e) {
+        var rect = canvas.getBoundingClientRect();
+        editor.points.push([(e.clientX - rect.left) / rect.width, (e.clientY - rect.top) / rect.height]);
+        regionDraw(cam);
+    };
+    var img = document.getElementById(cam + "regionimg");
+    img.onload = () => regionDraw(cam);
+    img.onerror = () => setTimeout(() => img.src = "/api/" + cam + "/snapshot?t=" + Date.now(), 3000);
+    if(img.complete && img.naturalWidth == 0) img.onerror();
+    window.addEventListener("resize", () => regionDraw(cam));
+    regionDraw(cam);
+}
+
+function regionDraw(cam) {
+    var editor = regionEditors[cam];
+    var canvas = document.getElementById(cam + "regioncanvas");
+    canvas.width = canvas.clientWidth;
+    canvas.height = canvas.clientHeight;
+    var ctx = canvas.getContext("2d");
+    ctx.clearRect(0, 0, canvas.width, canvas.height);
+    var poly = function(points, color, close) {
+        if(points.length == 0) return;
+        ctx.beginPath();
+        points.forEach((p, i) => i == 0 ? ctx.moveTo(p[0] * canvas.width, p[1] * canvas.height) : ctx.lineTo(p[0] * canvas.width, p[1] * canvas.height));
+        if(close) {
+            ctx.closePath();
+            ctx.fillStyle = color;
+            ctx.fill();
+        }
+        ctx.strokeStyle = color;
+        ctx.stroke();
+    };
+    editor.regions.forEach(r => {
+        poly(r.points, r.kind == "exclude" ? "rgba(255, 0, 0, 0.4)" : "rgba(0, 255, 0, 0.4)", true);
+        ctx.fillStyle = "white";
+        ctx.fillText(r.name, r.points[0][0] * canvas.width + 3, r.points[0][1] * canvas.height + 12);
+    });
+    poly(editor.points, "yellow", false);
+}
+
+function regionAdd(cam) {
+    var editor = regionEditors[cam];
+    var name = document.getElementById(cam + "regionname").value.trim();
+    if(name.length == 0 || editor.points.length < 3) {
+        window.alert("A region needs a name and at least 3 points");
+        return;
+    }
+    editor.regions.push({name: name, kind: document.getElementById(cam + "regionkind").value, points: editor.points});
+    editor.points = [];
+    regionDraw(cam);
+}
+
+function regionUndo(cam) {
+    var editor = regionEditors[cam];
+    if(editor.points.length > 0) {
+        editor.points.pop();
+    } else {
+        editor.regions.pop();
+    }
+    regionDraw(cam);
+}
+
+async function regionSave(cam) {
+    await adminUpdate(cam, {regions: regionEditors[cam].regions});
+}